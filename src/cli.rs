@@ -1,8 +1,12 @@
+use crate::ds_rpc::FinalityTag;
+use crate::ds_validate::ValidationMode;
+use crate::firehose::ChainProfile;
+
 #[derive(clap::Parser)]
 pub struct Cli {
     /// Subsquid portal endpoint URL
     #[clap(long)]
-    pub portal: String,
+    pub portal: Option<String>,
 
     /// Rpc api URL of an ethereum node
     #[clap(long)]
@@ -11,4 +15,44 @@ pub struct Cli {
     /// Number of blocks after which data is considered final
     #[clap(long)]
     pub finality_confirmation: Option<u64>,
+
+    /// Block tag which is used to determine final data instead of the confirmation count
+    #[clap(long, value_enum, conflicts_with = "finality_confirmation")]
+    pub finality_tag: Option<FinalityTag>,
+
+    /// Maximum number of blocks a chain reorg can replace
+    #[clap(long, default_value_t = 256)]
+    pub max_reorg_depth: u64,
+
+    /// Check numbering and parent hashes of finalized blocks of both data sources
+    #[clap(long, value_enum)]
+    pub validation_mode: Option<ValidationMode>,
+
+    /// Verify that hashes of finalized block headers match their content
+    #[clap(long)]
+    pub verify_header_hashes: bool,
+
+    /// Verify transactions and receipts roots of blocks requested one by one
+    #[clap(long, requires = "chain_id")]
+    pub verify_roots: bool,
+
+    /// Reject blocks which miss size, sha3 uncles, mix hash or nonce header fields
+    #[clap(long)]
+    pub strict_headers: bool,
+
+    /// Recover public keys of transaction senders and check them against from addresses
+    #[clap(long, requires = "chain_id")]
+    pub recover_public_keys: bool,
+
+    /// Include uncle headers into blocks, only rpc nodes serve them
+    #[clap(long)]
+    pub uncles: bool,
+
+    /// Chain whose transaction types and system transactions are converted
+    #[clap(long, value_enum, default_value_t = ChainProfile::Ethereum)]
+    pub chain_profile: ChainProfile,
+
+    /// Chain id which is used to encode typed transactions
+    #[clap(long)]
+    pub chain_id: Option<u64>,
 }
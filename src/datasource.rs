@@ -1,4 +1,8 @@
 use futures_core::stream::Stream;
+use std::collections::HashMap;
+
+// keccak hash of the rlp encoded empty uncles list
+pub const EMPTY_UNCLES_HASH: &str = "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347";
 
 #[derive(Debug, Clone, Default)]
 pub struct LogRequest {
@@ -7,6 +11,7 @@ pub struct LogRequest {
     pub transaction: bool,
     pub transaction_traces: bool,
     pub transaction_logs: bool,
+    pub transaction_state_diffs: bool,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -14,6 +19,7 @@ pub struct TxRequest {
     pub address: Vec<String>,
     pub sighash: Vec<String>,
     pub traces: bool,
+    pub state_diffs: bool,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -22,6 +28,7 @@ pub struct TraceRequest {
     pub sighash: Vec<String>,
     pub transaction: bool,
     pub transaction_logs: bool,
+    pub transaction_state_diffs: bool,
     pub parents: bool,
 }
 
@@ -32,6 +39,15 @@ pub struct DataRequest {
     pub logs: Vec<LogRequest>,
     pub transactions: Vec<TxRequest>,
     pub traces: Vec<TraceRequest>,
+    pub uncles: bool,
+}
+
+impl DataRequest {
+    pub fn has_state_diffs(&self) -> bool {
+        self.logs.iter().any(|r| r.transaction_state_diffs)
+            || self.transactions.iter().any(|r| r.state_diffs)
+            || self.traces.iter().any(|r| r.transaction_state_diffs)
+    }
 }
 
 #[derive(Debug)]
@@ -39,22 +55,44 @@ pub struct BlockHeader {
     pub number: u64,
     pub hash: String,
     pub parent_hash: String,
-    pub size: u64,
-    pub sha3_uncles: String,
+    // fields which some chains and post-merge datasets omit
+    pub size: Option<u64>,
+    pub sha3_uncles: Option<String>,
     pub miner: String,
     pub state_root: String,
     pub transactions_root: String,
     pub receipts_root: String,
     pub logs_bloom: String,
     pub difficulty: String,
-    pub total_difficulty: String,
+    pub total_difficulty: Option<String>,
     pub gas_limit: String,
     pub gas_used: String,
     pub timestamp: u64,
     pub extra_data: String,
-    pub mix_hash: String,
-    pub nonce: String,
+    pub mix_hash: Option<String>,
+    pub nonce: Option<String>,
     pub base_fee_per_gas: Option<String>,
+    pub withdrawals_root: Option<String>,
+    pub blob_gas_used: Option<String>,
+    pub excess_blob_gas: Option<String>,
+    pub parent_beacon_block_root: Option<String>,
+    pub requests_hash: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct AccessListItem {
+    pub address: String,
+    pub storage_keys: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Authorization {
+    pub chain_id: u64,
+    pub address: String,
+    pub nonce: u64,
+    pub y_parity: u8,
+    pub r: String,
+    pub s: String,
 }
 
 #[derive(Debug)]
@@ -78,7 +116,20 @@ pub struct Transaction {
     pub cumulative_gas_used: String,
     pub effective_gas_price: String,
     pub r#type: i32,
-    pub status: i32,
+    // receipts before byzantium have no status
+    pub status: Option<i32>,
+    // but the post-transaction state root instead
+    pub state_root: Option<String>,
+    pub logs_bloom: Option<String>,
+    pub access_list: Option<Vec<AccessListItem>>,
+    pub max_fee_per_blob_gas: Option<String>,
+    pub blob_versioned_hashes: Option<Vec<String>>,
+    pub authorization_list: Option<Vec<Authorization>>,
+    // l1 data fee of op stack chains
+    pub l1_fee: Option<String>,
+    pub l1_gas_price: Option<String>,
+    pub l1_gas_used: Option<String>,
+    pub l1_fee_scalar: Option<String>,
 }
 
 #[derive(Debug)]
@@ -106,6 +157,14 @@ pub enum CallType {
     Staticcall,
 }
 
+#[derive(Debug)]
+pub enum RewardType {
+    Block,
+    Uncle,
+    EmptyStep,
+    External,
+}
+
 #[derive(Debug)]
 pub struct TraceAction {
     pub from: Option<String>,
@@ -114,6 +173,7 @@ pub struct TraceAction {
     pub gas: Option<String>,
     pub input: Option<String>,
     pub r#type: Option<CallType>,
+    pub reward_type: Option<RewardType>,
 }
 
 #[derive(Clone, Debug)]
@@ -126,6 +186,7 @@ pub struct TraceResult {
 #[derive(Debug)]
 pub struct Trace {
     pub transaction_index: u32,
+    pub trace_address: Vec<u32>,
     pub r#type: TraceType,
     pub error: Option<String>,
     pub revert_reason: Option<String>,
@@ -133,12 +194,45 @@ pub struct Trace {
     pub result: Option<TraceResult>,
 }
 
+#[derive(Debug)]
+pub enum StateDiffKind {
+    NoChange,
+    Add,
+    Change,
+    Delete,
+}
+
+#[derive(Debug)]
+pub struct StateDiff {
+    pub transaction_index: u32,
+    pub address: String,
+    // "balance", "code", "nonce" or a storage slot
+    pub key: String,
+    pub kind: StateDiffKind,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Withdrawal {
+    pub index: u64,
+    pub validator_index: u64,
+    pub address: String,
+    // amount in gwei
+    pub amount: String,
+}
+
 #[derive(Debug)]
 pub struct Block {
     pub header: BlockHeader,
     pub logs: Vec<Log>,
     pub transactions: Vec<Transaction>,
     pub traces: Vec<Trace>,
+    pub state_diffs: Vec<StateDiff>,
+    pub withdrawals: Vec<Withdrawal>,
+    pub uncles: Vec<BlockHeader>,
+    // balances of reward and withdrawal recipients at the end of the block
+    pub balances: HashMap<String, String>,
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -175,6 +269,11 @@ pub trait DataSource {
     ) -> anyhow::Result<BlockStream>;
     async fn get_finalized_height(&self) -> anyhow::Result<u64>;
     async fn get_block_hash(&self, height: u64) -> anyhow::Result<String>;
+    async fn get_balances(
+        &self,
+        height: u64,
+        addresses: Vec<String>,
+    ) -> anyhow::Result<HashMap<String, String>>;
 }
 
 #[async_trait::async_trait]
@@ -188,3 +287,43 @@ pub trait HotSource: DataSource {
 }
 
 pub trait HotDataSource: DataSource + HotSource {}
+
+/// Block with the given numbering and empty contents
+#[cfg(test)]
+pub(crate) fn test_block(number: u64, hash: String, parent_hash: String) -> Block {
+    Block {
+        header: BlockHeader {
+            number,
+            hash,
+            parent_hash,
+            size: None,
+            sha3_uncles: None,
+            miner: String::new(),
+            state_root: String::new(),
+            transactions_root: String::new(),
+            receipts_root: String::new(),
+            logs_bloom: String::new(),
+            difficulty: String::new(),
+            total_difficulty: None,
+            gas_limit: String::new(),
+            gas_used: String::new(),
+            timestamp: 0,
+            extra_data: String::new(),
+            mix_hash: None,
+            nonce: None,
+            base_fee_per_gas: None,
+            withdrawals_root: None,
+            blob_gas_used: None,
+            excess_blob_gas: None,
+            parent_beacon_block_root: None,
+            requests_hash: None,
+        },
+        logs: vec![],
+        transactions: vec![],
+        traces: vec![],
+        state_diffs: vec![],
+        withdrawals: vec![],
+        uncles: vec![],
+        balances: HashMap::new(),
+    }
+}
@@ -1,27 +1,31 @@
 use crate::datasource::{
-    Block, BlockHeader, BlockStream, CallType, DataRequest, DataSource, Log, Trace, TraceAction,
-    TraceResult, TraceType, Transaction,
+    AccessListItem, Authorization, Block, BlockHeader, BlockStream, CallType, DataRequest,
+    DataSource, Log, RewardType, StateDiff, StateDiffKind, Trace, TraceAction, TraceResult,
+    TraceType, Transaction,
 };
+use crate::firehose::ChainProfile;
 use crate::{
     portal,
     portal::{
         Portal, Query, BlockFieldSelection, FieldSelection, LogFieldSelection, LogRequest,
-        TraceFieldSelection, TxFieldSelection, TraceRequest, TxRequest
+        StateDiffFieldSelection, TraceFieldSelection, TxFieldSelection, TraceRequest, TxRequest
     },
 };
 use async_stream::try_stream;
 use serde_json::Number;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Debug)]
 pub struct PortalDataSource {
     portal: Arc<Portal>,
+    chain: ChainProfile,
 }
 
 impl PortalDataSource {
-    pub fn new(portal: Arc<Portal>) -> PortalDataSource {
-        PortalDataSource { portal }
+    pub fn new(portal: Arc<Portal>, chain: ChainProfile) -> PortalDataSource {
+        PortalDataSource { portal, chain }
     }
 }
 
@@ -32,6 +36,7 @@ impl DataSource for PortalDataSource {
         request: DataRequest,
         stop_on_head: bool,
     ) -> anyhow::Result<BlockStream> {
+        let l1_fees = self.chain == ChainProfile::Optimism;
         let mut fields = FieldSelection {
             block: Some(BlockFieldSelection {
                 base_fee_per_gas: true,
@@ -53,12 +58,29 @@ impl DataSource for PortalDataSource {
                 state_root: true,
                 timestamp: true,
                 transactions_root: true,
+                withdrawals_root: true,
+                blob_gas_used: true,
+                excess_blob_gas: true,
+                parent_beacon_block_root: true,
+                requests_hash: true,
             }),
             log: None,
             transaction: None,
             trace: None,
+            state_diff: None,
         };
 
+        if request.has_state_diffs() {
+            fields.state_diff = Some(StateDiffFieldSelection {
+                transaction_index: true,
+                address: true,
+                key: true,
+                kind: true,
+                prev: true,
+                next: true,
+            });
+        }
+
         let logs = if request.logs.is_empty() {
             None
         } else {
@@ -84,6 +106,15 @@ impl DataSource for PortalDataSource {
                 s: true,
                 hash: true,
                 status: true,
+                logs_bloom: true,
+                access_list: true,
+                max_fee_per_blob_gas: true,
+                blob_versioned_hashes: true,
+                authorization_list: true,
+                l1_fee: l1_fees,
+                l1_gas_price: l1_fees,
+                l1_gas_used: l1_fees,
+                l1_fee_scalar: l1_fees,
                 to: true,
                 transaction_index: true,
                 r#type: true,
@@ -92,13 +123,16 @@ impl DataSource for PortalDataSource {
                 y_parity: true,
             });
             fields.trace = Some(TraceFieldSelection {
+                trace_address: true,
                 r#type: true,
                 error: true,
                 create_from: true,
                 create_value: true,
                 create_gas: true,
+                create_init: true,
                 create_result_gas_used: true,
                 create_result_address: true,
+                create_result_code: true,
                 call_from: true,
                 call_to: true,
                 call_value: true,
@@ -107,6 +141,12 @@ impl DataSource for PortalDataSource {
                 call_type: true,
                 call_result_gas_used: true,
                 call_result_output: true,
+                suicide_address: true,
+                suicide_refund_address: true,
+                suicide_balance: true,
+                reward_author: true,
+                reward_value: true,
+                reward_type: true,
             });
             let logs = request
                 .logs
@@ -117,6 +157,7 @@ impl DataSource for PortalDataSource {
                     transaction: r.transaction,
                     transaction_traces: r.transaction_traces,
                     transaction_logs: r.transaction_logs,
+                    transaction_state_diffs: r.transaction_state_diffs,
                 })
                 .collect();
             Some(logs)
@@ -140,6 +181,15 @@ impl DataSource for PortalDataSource {
                 s: true,
                 hash: true,
                 status: true,
+                logs_bloom: true,
+                access_list: true,
+                max_fee_per_blob_gas: true,
+                blob_versioned_hashes: true,
+                authorization_list: true,
+                l1_fee: l1_fees,
+                l1_gas_price: l1_fees,
+                l1_gas_used: l1_fees,
+                l1_fee_scalar: l1_fees,
                 to: true,
                 transaction_index: true,
                 r#type: true,
@@ -154,6 +204,7 @@ impl DataSource for PortalDataSource {
                     to: r.address,
                     sighash: r.sighash,
                     traces: r.traces,
+                    state_diffs: r.state_diffs,
                 })
                 .collect();
             Some(transactions)
@@ -177,6 +228,15 @@ impl DataSource for PortalDataSource {
                 s: true,
                 hash: true,
                 status: true,
+                logs_bloom: true,
+                access_list: true,
+                max_fee_per_blob_gas: true,
+                blob_versioned_hashes: true,
+                authorization_list: true,
+                l1_fee: l1_fees,
+                l1_gas_price: l1_fees,
+                l1_gas_used: l1_fees,
+                l1_fee_scalar: l1_fees,
                 to: true,
                 transaction_index: true,
                 r#type: true,
@@ -185,13 +245,16 @@ impl DataSource for PortalDataSource {
                 y_parity: true,
             });
             fields.trace = Some(TraceFieldSelection {
+                trace_address: true,
                 r#type: true,
                 error: true,
                 create_from: true,
                 create_value: true,
                 create_gas: true,
+                create_init: true,
                 create_result_gas_used: true,
                 create_result_address: true,
+                create_result_code: true,
                 call_from: true,
                 call_to: true,
                 call_value: true,
@@ -200,6 +263,12 @@ impl DataSource for PortalDataSource {
                 call_type: true,
                 call_result_gas_used: true,
                 call_result_output: true,
+                suicide_address: true,
+                suicide_refund_address: true,
+                suicide_balance: true,
+                reward_author: true,
+                reward_value: true,
+                reward_type: true,
             });
             if request.traces.iter().any(|r| r.transaction_logs) {
                 fields.log = Some(LogFieldSelection {
@@ -219,6 +288,7 @@ impl DataSource for PortalDataSource {
                     transaction: r.transaction,
                     parents: r.parents,
                     transaction_logs: r.transaction_logs,
+                    transaction_state_diffs: r.transaction_state_diffs,
                 })
                 .collect();
             Some(traces)
@@ -269,6 +339,14 @@ impl DataSource for PortalDataSource {
     async fn get_block_hash(&self, _height: u64) -> anyhow::Result<String> {
         todo!()
     }
+
+    async fn get_balances(
+        &self,
+        _height: u64,
+        _addresses: Vec<String>,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        anyhow::bail!("the portal doesn't serve balances")
+    }
 }
 
 fn to_u64(value: Number) -> u64 {
@@ -303,6 +381,11 @@ impl From<portal::BlockHeader> for BlockHeader {
             mix_hash: value.mix_hash,
             nonce: value.nonce,
             base_fee_per_gas: value.base_fee_per_gas,
+            withdrawals_root: value.withdrawals_root,
+            blob_gas_used: value.blob_gas_used,
+            excess_blob_gas: value.excess_blob_gas,
+            parent_beacon_block_root: value.parent_beacon_block_root,
+            requests_hash: value.requests_hash,
         }
     }
 }
@@ -342,6 +425,36 @@ impl From<portal::Transaction> for Transaction {
             effective_gas_price: value.effective_gas_price,
             r#type: value.r#type,
             status: value.status,
+            state_root: None,
+            logs_bloom: value.logs_bloom,
+            access_list: value.access_list.map(|access_list| {
+                access_list
+                    .into_iter()
+                    .map(|item| AccessListItem {
+                        address: item.address,
+                        storage_keys: item.storage_keys,
+                    })
+                    .collect()
+            }),
+            max_fee_per_blob_gas: value.max_fee_per_blob_gas,
+            blob_versioned_hashes: value.blob_versioned_hashes,
+            authorization_list: value.authorization_list.map(|authorization_list| {
+                authorization_list
+                    .into_iter()
+                    .map(|auth| Authorization {
+                        chain_id: auth.chain_id,
+                        address: auth.address,
+                        nonce: auth.nonce,
+                        y_parity: auth.y_parity,
+                        r: auth.r,
+                        s: auth.s,
+                    })
+                    .collect()
+            }),
+            l1_fee: value.l1_fee,
+            l1_gas_price: value.l1_gas_price,
+            l1_gas_used: value.l1_gas_used,
+            l1_fee_scalar: value.l1_fee_scalar.map(|scalar| scalar.to_string()),
         }
     }
 }
@@ -368,15 +481,35 @@ impl From<portal::CallType> for CallType {
     }
 }
 
+impl From<portal::RewardType> for RewardType {
+    fn from(value: portal::RewardType) -> Self {
+        match value {
+            portal::RewardType::Block => RewardType::Block,
+            portal::RewardType::Uncle => RewardType::Uncle,
+            portal::RewardType::EmptyStep => RewardType::EmptyStep,
+            portal::RewardType::External => RewardType::External,
+        }
+    }
+}
+
 impl From<portal::TraceAction> for TraceAction {
     fn from(value: portal::TraceAction) -> Self {
+        let (r#type, reward_type) = match value.r#type {
+            Some(portal::ActionType::Call(r#type)) => (Some(CallType::from(r#type)), None),
+            Some(portal::ActionType::Reward(r#type)) => (None, Some(RewardType::from(r#type))),
+            None => (None, None),
+        };
+        // self-destructs send the balance of the contract to the refund address
+        // and rewards are sent to the block author,
+        // creations carry the init code instead of the input
         TraceAction {
-            from: value.from,
-            to: value.to,
-            value: value.value,
+            from: value.from.or(value.address),
+            to: value.to.or(value.refund_address).or(value.author),
+            value: value.value.or(value.balance),
             gas: value.gas,
-            input: value.input,
-            r#type: value.r#type.and_then(|r#type| Some(CallType::from(r#type))),
+            input: value.input.or(value.init),
+            r#type,
+            reward_type,
         }
     }
 }
@@ -386,7 +519,7 @@ impl From<portal::TraceResult> for TraceResult {
         TraceResult {
             gas_used: value.gas_used,
             address: value.address,
-            output: value.output,
+            output: value.output.or(value.code),
         }
     }
 }
@@ -394,16 +527,35 @@ impl From<portal::TraceResult> for TraceResult {
 impl From<portal::Trace> for Trace {
     fn from(value: portal::Trace) -> Self {
         Trace {
-            transaction_index: value.transaction_index,
+            transaction_index: value.transaction_index.unwrap_or_default(),
+            trace_address: value.trace_address,
             r#type: TraceType::from(value.r#type),
             error: value.error,
             revert_reason: value.revert_reason,
             action: value
                 .action
-                .and_then(|action| Some(TraceAction::from(action))),
+                .map(TraceAction::from),
             result: value
                 .result
-                .and_then(|result| Some(TraceResult::from(result))),
+                .map(TraceResult::from),
+        }
+    }
+}
+
+impl From<portal::StateDiff> for StateDiff {
+    fn from(value: portal::StateDiff) -> Self {
+        StateDiff {
+            transaction_index: value.transaction_index,
+            address: value.address,
+            key: value.key,
+            kind: match value.kind {
+                portal::StateDiffKind::NoChange => StateDiffKind::NoChange,
+                portal::StateDiffKind::Add => StateDiffKind::Add,
+                portal::StateDiffKind::Change => StateDiffKind::Change,
+                portal::StateDiffKind::Delete => StateDiffKind::Delete,
+            },
+            prev: value.prev,
+            next: value.next,
         }
     }
 }
@@ -416,20 +568,30 @@ impl From<portal::Block> for Block {
                 .logs
                 .unwrap_or_default()
                 .into_iter()
-                .map(|l| Log::from(l))
+                .map(Log::from)
                 .collect(),
             transactions: value
                 .transactions
                 .unwrap_or_default()
                 .into_iter()
-                .map(|t| Transaction::from(t))
+                .map(Transaction::from)
                 .collect(),
             traces: value
                 .traces
                 .unwrap_or_default()
                 .into_iter()
-                .map(|t| Trace::from(t))
+                .map(Trace::from)
+                .collect(),
+            state_diffs: value
+                .state_diffs
+                .unwrap_or_default()
+                .into_iter()
+                .map(StateDiff::from)
                 .collect(),
+            // the portal doesn't serve withdrawals, uncles and balances
+            withdrawals: vec![],
+            uncles: vec![],
+            balances: HashMap::new(),
         }
     }
 }
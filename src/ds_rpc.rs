@@ -1,43 +1,277 @@
 use crate::datasource::{
-    Block, BlockHeader, BlockStream, CallType, DataRequest, DataSource, HashAndHeight,
-    HotBlockStream, HotDataSource, HotSource, HotUpdate, Log, LogRequest, Trace, TraceAction,
-    TraceResult, TraceType, Transaction, TraceRequest,
+    AccessListItem, Authorization, Block, BlockHeader, BlockStream, CallType, DataRequest,
+    DataSource, HashAndHeight, HotBlockStream, HotDataSource, HotSource, Log, LogRequest,
+    RewardType, StateDiff, StateDiffKind, Trace, TraceAction, TraceRequest, TraceResult, TraceType,
+    Transaction, TxRequest, Withdrawal, EMPTY_UNCLES_HASH,
 };
+use crate::follower::{self, ChainFollower};
 use anyhow::Context;
 use async_stream::try_stream;
 use ethers_core::types as evm;
-use ethers_providers::{Http, Middleware, Provider};
-use futures_core::Stream;
+use ethers_providers::{Http, Middleware, Provider, ProviderError};
 use futures_util::future::join_all;
 use prefix_hex::ToHexPrefixed;
-use std::cmp::min;
+use std::cmp::{max, min};
 use std::collections::{HashMap, HashSet};
 use std::future::Future;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, OnceCell};
+use tracing::{debug, warn};
 
 type Range = (u64, u64);
 
-async fn get_finalized_height(
-    height_tracker: &HeightTracker,
-    finality_confirmation: u64,
-) -> anyhow::Result<u64> {
-    let height = height_tracker.height().await?;
-    Ok(height.saturating_sub(finality_confirmation))
+pub(crate) const STRIDE_SIZE: u64 = 100;
+
+// strides of sparse filters grow up to this size, it's also the number of blocks fetched at once
+const MAX_STRIDE_SIZE: u64 = 500;
+
+// ranges with fewer results than this are considered sparse
+const SPARSE_RANGE_RESULTS: usize = 1000;
+
+// the stride is doubled only after this many sparse ranges in a row
+// to not hit the limit again right after it was bisected
+const SPARSE_RANGES_TO_GROW: u32 = 4;
+
+// error messages which nodes and providers use to reject too heavy eth_getLogs/trace_filter calls
+const RANGE_LIMIT_ERRORS: &[&str] = &[
+    "query returned more than",
+    "too many results",
+    "log response size exceeded",
+    "block range is too wide",
+    "block range too large",
+    "block range limit exceeded",
+    "exceed maximum block range",
+    "query exceeds max results",
+];
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum FinalityTag {
+    Finalized,
+    Safe,
+}
+
+impl From<FinalityTag> for evm::BlockNumber {
+    fn from(value: FinalityTag) -> Self {
+        match value {
+            FinalityTag::Finalized => evm::BlockNumber::Finalized,
+            FinalityTag::Safe => evm::BlockNumber::Safe,
+        }
+    }
+}
+
+// maximum number of attempts to get a consistent response from a node
+pub(crate) const MAX_RETRIES: u64 = 10;
+
+/// Inconsistent node response which is expected to be resolved by retrying the request,
+/// e.g. when a node behind a load balancer lags behind its siblings
+#[derive(Debug)]
+pub enum ConsistencyError {
+    BlockNotFound(evm::BlockId),
+    ReceiptNotFound(evm::H256),
+    TracesNotFound(evm::H256),
+    MissingField(&'static str),
+    ForeignReceipt(evm::H256),
+    ForeignTraces(evm::H256),
+    ForeignReplays(evm::H256),
+    UncleNotFound(evm::H256, usize),
+}
+
+impl std::fmt::Display for ConsistencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConsistencyError::BlockNotFound(id) => write!(f, "block {:?} not found", id),
+            ConsistencyError::ReceiptNotFound(hash) => {
+                write!(f, "receipt of tx {:?} not found", hash)
+            }
+            ConsistencyError::TracesNotFound(hash) => {
+                write!(f, "traces of tx {:?} not found", hash)
+            }
+            ConsistencyError::MissingField(field) => {
+                write!(f, "{} is missing in the response", field)
+            }
+            ConsistencyError::ForeignReceipt(hash) => {
+                write!(f, "receipt of tx {:?} belongs to another block", hash)
+            }
+            ConsistencyError::ForeignTraces(hash) => {
+                write!(f, "traces of block {:?} belong to another block", hash)
+            }
+            ConsistencyError::ForeignReplays(hash) => {
+                write!(f, "replays of block {:?} belong to another block", hash)
+            }
+            ConsistencyError::UncleNotFound(hash, index) => {
+                write!(f, "uncle {} of block {:?} not found", index, hash)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConsistencyError {}
+
+pub(crate) fn is_retryable(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<ConsistencyError>().is_some()
+}
+
+pub(crate) async fn backoff(retries: u64) {
+    tokio::time::sleep(Duration::from_millis(200 * retries)).await;
+}
+
+pub(crate) async fn retry<T, C, F>(call: C) -> anyhow::Result<T>
+where
+    C: Fn() -> F,
+    F: Future<Output = anyhow::Result<T>>,
+{
+    let mut retries = 0;
+    loop {
+        match call().await {
+            Ok(value) => return Ok(value),
+            Err(err) if is_retryable(&err) && retries < MAX_RETRIES => {
+                retries += 1;
+                warn!("inconsistent rpc response, retrying: {}", err);
+                backoff(retries).await;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Finality {
+    /// Blocks deeper than the given number of confirmations are considered final
+    Confirmations(u64),
+    /// Blocks up to the one the node reports for the given tag are considered final
+    Tag(FinalityTag),
+}
+
+struct StrideHint {
+    stride: u64,
+    sparse_ranges: u32,
+}
+
+/// Block range sizes of eth_getLogs/trace_filter calls per filter which were accepted last time
+#[derive(Default)]
+pub(crate) struct StrideHints(Mutex<HashMap<String, StrideHint>>);
+
+impl StrideHints {
+    fn get(&self, key: &str) -> u64 {
+        let hints = self.0.lock().expect("stride hints lock is poisoned");
+        hints.get(key).map_or(STRIDE_SIZE, |hint| hint.stride)
+    }
+
+    fn shrink(&self, key: &str, stride: u64) -> u64 {
+        let stride = max(stride / 2, 1);
+        let mut hints = self.0.lock().expect("stride hints lock is poisoned");
+        hints.insert(
+            key.to_string(),
+            StrideHint {
+                stride,
+                sparse_ranges: 0,
+            },
+        );
+        stride
+    }
+
+    fn grow(&self, key: &str, stride: u64, results: usize) -> u64 {
+        let mut hints = self.0.lock().expect("stride hints lock is poisoned");
+        let hint = hints.entry(key.to_string()).or_insert(StrideHint {
+            stride,
+            sparse_ranges: 0,
+        });
+        if results < SPARSE_RANGE_RESULTS {
+            hint.sparse_ranges += 1;
+        } else {
+            hint.sparse_ranges = 0;
+        }
+        if hint.sparse_ranges >= SPARSE_RANGES_TO_GROW {
+            hint.stride = min(hint.stride * 2, MAX_STRIDE_SIZE);
+            hint.sparse_ranges = 0;
+        }
+        hint.stride
+    }
+
+    /// Size of block ranges the request is split into, strides below the default
+    /// are handled by bisecting the ranges so they don't shrink the ranges themselves
+    pub(crate) fn range_size(&self, request: &DataRequest) -> anyhow::Result<u64> {
+        let mut keys: Vec<_> = request.logs.iter().map(log_key).collect();
+        if !request.traces.is_empty() {
+            keys.push(trace_key(&trace_addresses(&request.traces)?));
+        }
+        let stride = keys
+            .iter()
+            .map(|key| self.get(key))
+            .min()
+            .unwrap_or(STRIDE_SIZE);
+        Ok(stride.clamp(STRIDE_SIZE, MAX_STRIDE_SIZE))
+    }
+}
+
+fn log_key(request: &LogRequest) -> String {
+    format!("logs:{:?}:{:?}", request.address, request.topic0)
+}
+
+fn trace_key(address: &[evm::H160]) -> String {
+    format!("traces:{:?}", address)
+}
+
+fn trace_addresses(requests: &[TraceRequest]) -> anyhow::Result<Vec<evm::H160>> {
+    let mut address = requests
+        .iter()
+        .flat_map(|request| request.address.clone())
+        .map(|address| address.parse::<evm::H160>())
+        .collect::<Result<Vec<_>, _>>()?;
+    address.sort();
+    address.dedup();
+    Ok(address)
+}
+
+fn is_range_limit_error(err: &ProviderError) -> bool {
+    let message = err.to_string().to_lowercase();
+    RANGE_LIMIT_ERRORS
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+async fn query_adaptive<T, Q, F>(
+    range: &Range,
+    hints: &StrideHints,
+    key: &str,
+    query: Q,
+) -> anyhow::Result<Vec<T>>
+where
+    Q: Fn(Range) -> F,
+    F: Future<Output = Result<Vec<T>, ProviderError>>,
+{
+    let mut items = vec![];
+    let mut stride = hints.get(key);
+    let mut from = range.0;
+    while from <= range.1 {
+        let to = min(from + stride - 1, range.1);
+        match query((from, to)).await {
+            Ok(mut result) => {
+                stride = hints.grow(key, stride, result.len());
+                items.append(&mut result);
+                from = to + 1;
+            }
+            Err(e) if to > from && is_range_limit_error(&e) => {
+                debug!("range {}-{} is too heavy for {}: {}", from, to, key, e);
+                stride = hints.shrink(key, to - from + 1);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(items)
 }
 
 async fn get_logs(
     client: &Provider<Http>,
     range: &Range,
     requests: &Vec<LogRequest>,
+    hints: &StrideHints,
 ) -> anyhow::Result<Vec<evm::Log>> {
     let mut logs = vec![];
 
     for request in requests {
-        let mut filter = evm::Filter::new()
-            .from_block(range.0)
-            .to_block(range.1);
+        let mut filter = evm::Filter::new();
 
         if !request.address.is_empty() {
             let address = request
@@ -57,7 +291,12 @@ async fn get_logs(
             filter = filter.topic0(topic);
         }
 
-        let mut request_logs = client.get_logs(&filter).await?;
+        let key = log_key(request);
+        let mut request_logs = query_adaptive(range, hints, &key, |(from, to)| {
+            let filter = filter.clone().from_block(from).to_block(to);
+            async move { client.get_logs(&filter).await }
+        })
+        .await?;
         logs.append(&mut request_logs);
     }
 
@@ -67,51 +306,79 @@ async fn get_logs(
 async fn get_traces(
     client: &Provider<Http>,
     range: &Range,
-    requests: &Vec<TraceRequest>
+    requests: &[TraceRequest],
+    hints: &StrideHints,
 ) -> anyhow::Result<Vec<evm::Trace>> {
     if requests.is_empty() {
-        return Ok(vec![])
+        return Ok(vec![]);
     }
 
-    let mut address = requests.iter()
-        .flat_map(|request| request.address.clone())
-        .map(|address| address.parse::<evm::H160>())
-        .collect::<Result<Vec<_>, _>>()?;
-    address.sort();
-    address.dedup();
+    let address = trace_addresses(requests)?;
+    let key = trace_key(&address);
+    let filter = evm::TraceFilter::default().to_address(address);
 
-    let filter = evm::TraceFilter::default()
-        .from_block(range.0)
-        .to_block(range.1)
-        .to_address(address);
+    let traces = query_adaptive(range, hints, &key, |(from, to)| {
+        let filter = filter.clone().from_block(from).to_block(to);
+        async move { client.trace_filter(filter).await }
+    })
+    .await?;
 
-    let mut traces: Vec<_> = client.trace_filter(filter).await?
+    let mut traces: Vec<_> = traces
         .into_iter()
-        .filter(|trace| {
-            if let evm::Action::Call(action) = &trace.action {
-                let to = format!("{:?}", action.to);
-                let input = action.input.to_hex_prefixed();
-                let sighash = to_sighash(&input);
-                if let Some(sighash) = sighash {
-                    for request in requests {
-                        if !request.address.contains(&to) {
-                            continue;
-                        }
-                        if request.sighash.contains(&sighash.to_string()) {
-                            return true;
-                        }
-                    }
-                }
-            }
-            false
-        })
+        .filter(|trace| is_requested_trace(trace, requests))
         .collect();
 
-    traces.sort_by_key(|t| (t.transaction_hash.unwrap(), t.trace_address.clone()));
+    traces.sort_by_key(|t| (t.transaction_hash, t.trace_address.clone()));
 
     Ok(traces)
 }
 
+// a call has to match both address and sighash lists.
+// a request with both lists empty is the explicit "all" case (see `TraceRequest::default()`),
+// otherwise an empty list matches nothing.
+fn is_requested_call(
+    to: Option<&String>,
+    input: &str,
+    address: &[String],
+    sighash: &[String],
+) -> bool {
+    if address.is_empty() && sighash.is_empty() {
+        return true;
+    }
+    let address_matches = to.is_some_and(|to| address.contains(to));
+    let sighash_matches =
+        to_sighash(input).is_some_and(|value| sighash.iter().any(|sighash| sighash == value));
+    address_matches && sighash_matches
+}
+
+pub(crate) fn is_requested_trace(trace: &evm::Trace, requests: &[TraceRequest]) -> bool {
+    let (to, input) = match &trace.action {
+        evm::Action::Call(action) => (
+            Some(format!("{:?}", action.to)),
+            action.input.to_hex_prefixed(),
+        ),
+        evm::Action::Create(action) => (None, action.init.to_hex_prefixed()),
+        evm::Action::Suicide(_) => (None, String::new()),
+        // rewards don't belong to any transaction and are part of complete blocks only
+        evm::Action::Reward(_) => {
+            return requests
+                .iter()
+                .any(|request| request.address.is_empty() && request.sighash.is_empty())
+        }
+    };
+    requests
+        .iter()
+        .any(|request| is_requested_call(to.as_ref(), &input, &request.address, &request.sighash))
+}
+
+pub(crate) fn is_requested_tx(tx: &evm::Transaction, requests: &[TxRequest]) -> bool {
+    let to = tx.to.map(|to| format!("{:?}", to));
+    let input = tx.input.to_hex_prefixed();
+    requests
+        .iter()
+        .any(|request| is_requested_call(to.as_ref(), &input, &request.address, &request.sighash))
+}
+
 fn to_sighash(input: &str) -> Option<&str> {
     if input.len() >= 10 {
         Some(&input[..10])
@@ -120,13 +387,14 @@ fn to_sighash(input: &str) -> Option<&str> {
     }
 }
 
-async fn get_stride(
+pub(crate) async fn get_stride(
     client: &Provider<Http>,
     range: &Range,
     request: &DataRequest,
+    hints: &StrideHints,
 ) -> anyhow::Result<Vec<Block>> {
     let rpc_blocks = get_blocks(client, range).await?;
-    let blocks = get_requested_data(client, rpc_blocks, request).await?;
+    let blocks = get_requested_data(client, rpc_blocks, request, hints).await?;
     Ok(blocks)
 }
 
@@ -140,7 +408,8 @@ async fn get_blocks(
     join_all(futures)
         .await
         .into_iter()
-        .map(|res| Ok(res?.expect("unfinalized block was requested")))
+        .zip(range.0..=range.1)
+        .map(|(res, num)| Ok(res?.ok_or(ConsistencyError::BlockNotFound(num.into()))?))
         .collect()
 }
 
@@ -148,51 +417,89 @@ async fn get_requested_data(
     client: &Provider<Http>,
     mut blocks: Vec<evm::Block<evm::Transaction>>,
     request: &DataRequest,
+    hints: &StrideHints,
 ) -> anyhow::Result<Vec<Block>> {
     if blocks.is_empty() {
         return Ok(vec![]);
     }
 
+    let mut hash_by_block = HashMap::new();
+    for block in &blocks {
+        let number = block
+            .number
+            .ok_or(ConsistencyError::MissingField("block number"))?;
+        let hash = block
+            .hash
+            .ok_or(ConsistencyError::MissingField("block hash"))?;
+        hash_by_block.insert(number.as_u64(), hash);
+    }
+
     let range = (
-        blocks.first().unwrap().number.unwrap().as_u64(),
-        blocks.last().unwrap().number.unwrap().as_u64(),
+        *hash_by_block.keys().min().unwrap(),
+        *hash_by_block.keys().max().unwrap(),
     );
 
-    let logs = get_logs(client, &range, &request.logs).await?;
-    let traces = get_traces(client, &range, &request.traces).await?;
+    let logs = get_logs(client, &range, &request.logs, hints).await?;
+    let traces = get_traces(client, &range, &request.traces, hints).await?;
 
     let mut tx_hashes = HashSet::new();
     let mut has_root_trace: HashMap<evm::H256, bool> = HashMap::new();
 
     for log in logs {
-        let tx_hash = log.transaction_hash.unwrap().clone();
+        let tx_hash = log
+            .transaction_hash
+            .ok_or(ConsistencyError::MissingField("log transaction hash"))?;
         tx_hashes.insert(tx_hash);
     }
 
     let mut traces_by_block: HashMap<u64, Vec<evm::Trace>> = HashMap::new();
     for trace in traces {
-        let tx_hash = trace.transaction_hash.unwrap().clone();
+        // rewards don't belong to any transaction
+        if matches!(trace.action, evm::Action::Reward(_)) {
+            traces_by_block
+                .entry(trace.block_number)
+                .or_default()
+                .push(trace);
+            continue;
+        }
+        let tx_hash = trace
+            .transaction_hash
+            .ok_or(ConsistencyError::MissingField("trace transaction hash"))?;
         tx_hashes.insert(tx_hash);
 
         if trace.trace_address.is_empty() {
-            has_root_trace.insert(tx_hash.clone(), true);
+            has_root_trace.insert(tx_hash, true);
         }
 
         if has_root_trace.contains_key(&tx_hash) {
-            if traces_by_block.contains_key(&trace.block_number) {
-                traces_by_block.get_mut(&trace.block_number).unwrap().push(trace);
-            } else {
-                traces_by_block.insert(trace.block_number, vec![trace]);
-            }
+            traces_by_block
+                .entry(trace.block_number)
+                .or_default()
+                .push(trace);
         }
     }
 
     let mut tx_by_block = HashMap::new();
+    let mut block_tx_hashes = HashMap::new();
     for block in &mut blocks {
-        let block_num = block.number.unwrap().as_u64();
+        let block_num = block
+            .number
+            .expect("block number is checked above")
+            .as_u64();
         let mut transactions = vec![];
 
+        block_tx_hashes.insert(
+            block_num,
+            block
+                .transactions
+                .iter()
+                .map(|tx| tx.hash)
+                .collect::<Vec<_>>(),
+        );
         for tx in block.transactions.drain(..) {
+            if is_requested_tx(&tx, &request.transactions) {
+                tx_hashes.insert(tx.hash);
+            }
             if tx_hashes.contains(&tx.hash) {
                 transactions.push(tx);
             }
@@ -201,24 +508,60 @@ async fn get_requested_data(
         tx_by_block.insert(block_num, transactions);
     }
 
+    let mut state_diffs_by_block: HashMap<u64, Vec<StateDiff>> = HashMap::new();
+    if request.has_state_diffs() {
+        let futures: Vec<_> = tx_by_block
+            .iter()
+            .filter(|(_, transactions)| !transactions.is_empty())
+            .map(|(block_num, transactions)| {
+                let hash = hash_by_block[block_num];
+                let block_tx_hashes = &block_tx_hashes[block_num];
+                async move {
+                    let replays = client
+                        .trace_replay_block_transactions(
+                            (*block_num).into(),
+                            vec![evm::TraceType::StateDiff],
+                        )
+                        .await?;
+                    check_replays(hash, block_tx_hashes, &replays)?;
+                    let state_diffs = get_state_diffs(transactions.iter(), &replays)?;
+                    Ok::<_, anyhow::Error>((*block_num, state_diffs))
+                }
+            })
+            .collect();
+        for result in join_all(futures).await {
+            let (block_num, state_diffs) = result?;
+            state_diffs_by_block.insert(block_num, state_diffs);
+        }
+    }
+
     let futures: Vec<_> = tx_hashes
         .iter()
-        .map(|hash| client.get_transaction_receipt(*hash))
+        .map(|hash| async move {
+            let receipt = client.get_transaction_receipt(*hash).await?;
+            Ok::<_, anyhow::Error>(receipt.ok_or(ConsistencyError::ReceiptNotFound(*hash))?)
+        })
         .collect();
     let results = join_all(futures).await;
     let mut logs_by_block: HashMap<u64, Vec<evm::Log>> = HashMap::new();
     let mut receipt_by_hash: HashMap<evm::H256, evm::TransactionReceipt> = HashMap::new();
     for result in results {
-        let mut receipt = result?.unwrap();
-        let block_num = receipt.block_number.unwrap().as_u64();
-
-        if logs_by_block.contains_key(&block_num) {
-            logs_by_block.get_mut(&block_num).unwrap().append(&mut receipt.logs);
-        } else {
-            let logs = receipt.logs.drain(..).collect();
-            logs_by_block.insert(block_num, logs);
+        let mut receipt = result?;
+        let block_num = receipt
+            .block_number
+            .ok_or(ConsistencyError::MissingField("receipt block number"))?
+            .as_u64();
+        if receipt.block_hash.is_none()
+            || receipt.block_hash != hash_by_block.get(&block_num).copied()
+        {
+            Err(ConsistencyError::ForeignReceipt(receipt.transaction_hash))?;
         }
 
+        logs_by_block
+            .entry(block_num)
+            .or_default()
+            .append(&mut receipt.logs);
+
         receipt_by_hash.insert(receipt.transaction_hash, receipt);
     }
 
@@ -226,20 +569,37 @@ async fn get_requested_data(
         .iter()
         .filter_map(|hash| {
             if !has_root_trace.contains_key(hash) {
-                Some(client.trace_transaction(*hash))
+                Some(async move { (*hash, client.trace_transaction(*hash).await) })
             } else {
                 None
             }
         })
         .collect();
     let results = join_all(futures).await;
-    for result in results {
+    for (hash, result) in results {
         let mut traces = result?;
-        let call = &traces[0];
-        if traces_by_block.contains_key(&call.block_number) {
-            traces_by_block.get_mut(&call.block_number).unwrap().append(&mut traces);
-        } else {
-            traces_by_block.insert(call.block_number, traces);
+        let block_number = traces
+            .first()
+            .ok_or(ConsistencyError::TracesNotFound(hash))?
+            .block_number;
+        traces_by_block
+            .entry(block_number)
+            .or_default()
+            .append(&mut traces);
+    }
+
+    let mut uncles_by_block: HashMap<u64, Vec<BlockHeader>> = HashMap::new();
+    if request.uncles {
+        let futures: Vec<_> = blocks
+            .iter()
+            .map(|block| get_uncles(client, block))
+            .collect();
+        for (block, result) in blocks.iter().zip(join_all(futures).await) {
+            let block_num = block
+                .number
+                .expect("block number is checked above")
+                .as_u64();
+            uncles_by_block.insert(block_num, result?);
         }
     }
 
@@ -252,7 +612,7 @@ async fn get_requested_data(
                 .remove(&block.header.number)
                 .unwrap_or_default()
                 .into_iter()
-                .map(|log| Log::try_from(log))
+                .map(Log::try_from)
                 .collect::<Result<Vec<_>, _>>()?;
             logs.sort_by_key(|log| log.log_index);
 
@@ -261,7 +621,9 @@ async fn get_requested_data(
                 .unwrap_or_default()
                 .into_iter()
                 .map(|tx| {
-                    let receipt = receipt_by_hash.remove(&tx.hash).unwrap();
+                    let receipt = receipt_by_hash
+                        .remove(&tx.hash)
+                        .ok_or(ConsistencyError::ReceiptNotFound(tx.hash))?;
                     Transaction::try_from((tx, receipt))
                 })
                 .collect::<Result<Vec<_>, _>>()?;
@@ -271,12 +633,18 @@ async fn get_requested_data(
                 .remove(&block.header.number)
                 .unwrap_or_default()
                 .into_iter()
-                .map(|trace| Trace::try_from(trace))
+                .map(Trace::try_from)
                 .collect::<Result<Vec<_>, _>>()?;
 
             block.logs = logs;
             block.transactions = transactions;
             block.traces = traces;
+            block.state_diffs = state_diffs_by_block
+                .remove(&block.header.number)
+                .unwrap_or_default();
+            block.uncles = uncles_by_block
+                .remove(&block.header.number)
+                .unwrap_or_default();
 
             Ok(block)
         })
@@ -285,6 +653,106 @@ async fn get_requested_data(
     Ok(blocks)
 }
 
+fn to_state_diff<T>(
+    transaction_index: u32,
+    address: &str,
+    key: String,
+    diff: &evm::Diff<T>,
+    format: impl Fn(&T) -> String,
+) -> Option<StateDiff> {
+    let (kind, prev, next) = match diff {
+        evm::Diff::Same => return None,
+        evm::Diff::Born(val) => (StateDiffKind::Add, None, Some(format(val))),
+        evm::Diff::Died(val) => (StateDiffKind::Delete, Some(format(val)), None),
+        evm::Diff::Changed(val) => (
+            StateDiffKind::Change,
+            Some(format(&val.from)),
+            Some(format(&val.to)),
+        ),
+    };
+    Some(StateDiff {
+        transaction_index,
+        address: address.to_string(),
+        key,
+        kind,
+        prev,
+        next,
+    })
+}
+
+/// Checks that the replays are the ones of the block transactions
+/// as trace_replayBlockTransactions can only be requested by number
+pub(crate) fn check_replays(
+    hash: evm::H256,
+    transactions: &[evm::H256],
+    replays: &[evm::BlockTrace],
+) -> Result<(), ConsistencyError> {
+    let replayed = replays.iter().map(|replay| replay.transaction_hash);
+    if !replayed.eq(transactions.iter().map(|hash| Some(*hash))) {
+        return Err(ConsistencyError::ForeignReplays(hash));
+    }
+    Ok(())
+}
+
+/// Flattens state diffs of replayed transactions keeping only the given transactions
+pub(crate) fn get_state_diffs<'a>(
+    transactions: impl Iterator<Item = &'a evm::Transaction>,
+    replays: &[evm::BlockTrace],
+) -> anyhow::Result<Vec<StateDiff>> {
+    let mut indexes = HashMap::new();
+    for tx in transactions {
+        let index = tx
+            .transaction_index
+            .context("no transaction index")?
+            .as_u32();
+        indexes.insert(tx.hash, index);
+    }
+
+    let mut state_diffs = vec![];
+    for replay in replays {
+        let index = match replay.transaction_hash.and_then(|hash| indexes.get(&hash)) {
+            Some(index) => *index,
+            None => continue,
+        };
+        let accounts = match &replay.state_diff {
+            Some(state_diff) => &state_diff.0,
+            None => continue,
+        };
+        for (address, account) in accounts {
+            let address = format!("{:?}", address);
+            let qty = |val: &evm::U256| format!("{:#x}", val);
+            state_diffs.extend(to_state_diff(
+                index,
+                &address,
+                "balance".into(),
+                &account.balance,
+                qty,
+            ));
+            state_diffs.extend(to_state_diff(
+                index,
+                &address,
+                "nonce".into(),
+                &account.nonce,
+                qty,
+            ));
+            state_diffs.extend(to_state_diff(
+                index,
+                &address,
+                "code".into(),
+                &account.code,
+                |val| val.to_hex_prefixed(),
+            ));
+            for (slot, diff) in &account.storage {
+                let key = format!("{:?}", slot);
+                state_diffs.extend(to_state_diff(index, &address, key, diff, |val| {
+                    format!("{:?}", val)
+                }));
+            }
+        }
+    }
+    Ok(state_diffs)
+}
+
 impl TryFrom<&String> for TraceType {
     type Error = anyhow::Error;
 
@@ -329,7 +797,8 @@ impl TryFrom<evm::CallFrame> for Trace {
                     input: Some(value.input.to_hex_prefixed()),
                     to: Some(format!("{:?}", to)),
                     r#type: Some(CallType::try_from(&value.typ)?),
-                    value: value.value.and_then(|val| Some(format!("{:#x}", val))),
+                    value: value.value.map(|val| format!("{:#x}", val)),
+                    reward_type: None,
                 })
             }
             TraceType::Create => Some(TraceAction {
@@ -338,28 +807,44 @@ impl TryFrom<evm::CallFrame> for Trace {
                 input: Some(value.input.to_hex_prefixed()),
                 to: None,
                 r#type: None,
-                value: value.value.and_then(|val| Some(format!("{:#x}", val))),
+                value: value.value.map(|val| format!("{:#x}", val)),
+                reward_type: None,
             }),
-            TraceType::Suicide => None,
+            TraceType::Suicide => {
+                let to = match value.to.as_ref().context("no to")? {
+                    evm::NameOrAddress::Name(_) => anyhow::bail!("names aren't supported"),
+                    evm::NameOrAddress::Address(address) => address,
+                };
+                Some(TraceAction {
+                    from: Some(format!("{:?}", value.from)),
+                    gas: None,
+                    input: None,
+                    to: Some(format!("{:?}", to)),
+                    r#type: None,
+                    value: value.value.map(|val| format!("{:#x}", val)),
+                    reward_type: None,
+                })
+            }
             TraceType::Reward => unreachable!(),
         };
         let result = match r#type {
             TraceType::Call => Some(TraceResult {
                 address: None,
                 gas_used: Some(format!("{:#x}", value.gas_used)),
-                output: value.output.and_then(|val| Some(val.to_hex_prefixed())),
+                output: value.output.map(|val| val.to_hex_prefixed()),
             }),
             TraceType::Create => Some(TraceResult {
-                address: value.to.and_then(|val| Some(format!("{:?}", val))),
+                address: value.to.map(|val| format!("{:?}", val)),
                 gas_used: Some(format!("{:#x}", value.gas_used)),
-                output: value.output.and_then(|val| Some(val.to_hex_prefixed())),
+                output: value.output.map(|val| val.to_hex_prefixed()),
             }),
             TraceType::Suicide => None,
             TraceType::Reward => unreachable!(),
         };
 
         Ok(Trace {
-            transaction_index: 0, // call_frame has no info about its tx
+            transaction_index: 0,  // call_frame has no info about its tx
+            trace_address: vec![], // nor about its position in the call tree
             r#type,
             action,
             result,
@@ -369,40 +854,89 @@ impl TryFrom<evm::CallFrame> for Trace {
     }
 }
 
+/// Fetches uncle headers of a block unless its uncles hash commits to an empty list
+pub(crate) async fn get_uncles(
+    client: &Provider<Http>,
+    block: &evm::Block<evm::Transaction>,
+) -> anyhow::Result<Vec<BlockHeader>> {
+    if format!("{:?}", block.uncles_hash) == EMPTY_UNCLES_HASH {
+        return Ok(vec![]);
+    }
+    let hash = block
+        .hash
+        .ok_or(ConsistencyError::MissingField("block hash"))?;
+    let futures: Vec<_> = (0..block.uncles.len())
+        .map(|index| client.get_uncle(hash, evm::U64::from(index)))
+        .collect();
+    join_all(futures)
+        .await
+        .into_iter()
+        .enumerate()
+        .map(|(index, result)| {
+            let uncle = result?.ok_or(ConsistencyError::UncleNotFound(hash, index))?;
+            to_block_header(&uncle)
+        })
+        .collect()
+}
+
+fn to_block_header<T>(value: &evm::Block<T>) -> anyhow::Result<BlockHeader> {
+    Ok(BlockHeader {
+        number: value.number.context("no number")?.as_u64(),
+        hash: format!("{:?}", value.hash.context("no hash")?),
+        parent_hash: format!("{:?}", value.parent_hash),
+        size: value.size.map(|val| val.as_u64()),
+        sha3_uncles: Some(format!("{:?}", value.uncles_hash)),
+        miner: format!("{:?}", value.author.context("no author")?),
+        state_root: format!("{:?}", value.state_root),
+        transactions_root: format!("{:?}", value.transactions_root),
+        receipts_root: format!("{:?}", value.receipts_root),
+        logs_bloom: format!("{:?}", value.logs_bloom.context("no logs bloom")?),
+        difficulty: format!("{:#x}", value.difficulty),
+        total_difficulty: value.total_difficulty.map(|val| format!("{:#x}", val)),
+        gas_limit: format!("{:#x}", value.gas_limit),
+        gas_used: format!("{:#x}", value.gas_used),
+        timestamp: value.timestamp.as_u64(),
+        extra_data: value.extra_data.to_hex_prefixed(),
+        mix_hash: value.mix_hash.map(|val| format!("{:?}", val)),
+        nonce: value.nonce.map(|val| format!("{:?}", val)),
+        base_fee_per_gas: value.base_fee_per_gas.map(|val| format!("{:#x}", val)),
+        withdrawals_root: value.withdrawals_root.map(|val| format!("{:?}", val)),
+        blob_gas_used: value.blob_gas_used.map(|val| format!("{:#x}", val)),
+        excess_blob_gas: value.excess_blob_gas.map(|val| format!("{:#x}", val)),
+        parent_beacon_block_root: value
+            .parent_beacon_block_root
+            .map(|val| format!("{:?}", val)),
+        requests_hash: value
+            .other
+            .get("requestsHash")
+            .and_then(|val| val.as_str())
+            .map(|val| val.to_string()),
+    })
+}
+
 impl TryFrom<evm::Block<evm::Transaction>> for Block {
     type Error = anyhow::Error;
 
     fn try_from(value: evm::Block<evm::Transaction>) -> Result<Self, Self::Error> {
         Ok(Block {
-            header: BlockHeader {
-                number: value.number.context("no number")?.as_u64(),
-                hash: format!("{:?}", value.hash.context("no hash")?),
-                parent_hash: format!("{:?}", value.parent_hash),
-                size: value.size.context("no size")?.as_u64(),
-                sha3_uncles: format!("{:?}", value.uncles_hash),
-                miner: format!("{:?}", value.author.context("no author")?),
-                state_root: format!("{:?}", value.state_root),
-                transactions_root: format!("{:?}", value.transactions_root),
-                receipts_root: format!("{:?}", value.receipts_root),
-                logs_bloom: format!("{:?}", value.logs_bloom.context("no logs bloom")?),
-                difficulty: format!("{:#x}", value.difficulty),
-                total_difficulty: format!(
-                    "{:#x}",
-                    value.total_difficulty.context("no total difficulty")?
-                ),
-                gas_limit: format!("{:#x}", value.gas_limit),
-                gas_used: format!("{:#x}", value.gas_used),
-                timestamp: value.timestamp.as_u64(),
-                extra_data: value.extra_data.to_hex_prefixed(),
-                mix_hash: format!("{:?}", value.mix_hash.context("no mix hash")?),
-                nonce: format!("{:?}", value.nonce.context("no nonce")?),
-                base_fee_per_gas: value
-                    .base_fee_per_gas
-                    .and_then(|val| Some(format!("{:#x}", val))),
-            },
+            header: to_block_header(&value)?,
             logs: vec![],
             traces: vec![],
             transactions: vec![],
+            state_diffs: vec![],
+            withdrawals: value
+                .withdrawals
+                .unwrap_or_default()
+                .into_iter()
+                .map(|withdrawal| Withdrawal {
+                    index: withdrawal.index.as_u64(),
+                    validator_index: withdrawal.validator_index.as_u64(),
+                    address: format!("{:?}", withdrawal.address),
+                    amount: format!("{:#x}", withdrawal.amount),
+                })
+                .collect(),
+            uncles: vec![],
+            balances: HashMap::new(),
         })
     }
 }
@@ -428,22 +962,84 @@ impl TryFrom<evm::Log> for Log {
     }
 }
 
+// EIP-7702 authorization which ethers-core doesn't model yet
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcAuthorization {
+    chain_id: evm::U256,
+    address: evm::Address,
+    nonce: evm::U64,
+    y_parity: evm::U64,
+    r: evm::U256,
+    s: evm::U256,
+}
+
+impl TryFrom<RpcAuthorization> for Authorization {
+    type Error = anyhow::Error;
+
+    fn try_from(value: RpcAuthorization) -> Result<Self, Self::Error> {
+        Ok(Authorization {
+            chain_id: u64::try_from(value.chain_id).map_err(anyhow::Error::msg)?,
+            address: format!("{:?}", value.address),
+            nonce: value.nonce.as_u64(),
+            y_parity: u8::try_from(value.y_parity.as_u64())?,
+            r: format!("{:#x}", value.r),
+            s: format!("{:#x}", value.s),
+        })
+    }
+}
+
 impl TryFrom<(evm::Transaction, evm::TransactionReceipt)> for Transaction {
     type Error = anyhow::Error;
 
     fn try_from(value: (evm::Transaction, evm::TransactionReceipt)) -> Result<Self, Self::Error> {
         let tx = value.0;
         let receipt = value.1;
+        let max_fee_per_blob_gas = tx
+            .other
+            .get_deserialized::<evm::U256>("maxFeePerBlobGas")
+            .transpose()?;
+        let blob_versioned_hashes = tx
+            .other
+            .get_deserialized::<Vec<evm::H256>>("blobVersionedHashes")
+            .transpose()?;
+        let l1_fee = receipt
+            .other
+            .get_deserialized::<evm::U256>("l1Fee")
+            .transpose()?;
+        let l1_gas_price = receipt
+            .other
+            .get_deserialized::<evm::U256>("l1GasPrice")
+            .transpose()?;
+        let l1_gas_used = receipt
+            .other
+            .get_deserialized::<evm::U256>("l1GasUsed")
+            .transpose()?;
+        let l1_fee_scalar = receipt
+            .other
+            .get_deserialized::<String>("l1FeeScalar")
+            .transpose()?;
+        let authorization_list = tx
+            .other
+            .get_deserialized::<Vec<RpcAuthorization>>("authorizationList")
+            .transpose()?
+            .map(|list| {
+                list.into_iter()
+                    .map(Authorization::try_from)
+                    .collect::<anyhow::Result<Vec<_>>>()
+            })
+            .transpose()?;
         Ok(Transaction {
             hash: format!("{:?}", tx.hash),
             from: format!("{:?}", tx.from),
-            to: tx.to.and_then(|val| Some(format!("{:?}", val))),
+            to: tx.to.map(|val| format!("{:?}", val)),
             transaction_index: tx
                 .transaction_index
                 .context("no transaction index")?
                 .as_u32(),
             input: tx.input.to_hex_prefixed(),
-            r#type: i32::try_from(tx.transaction_type.context("no transaction type")?)
+            // nodes don't report the type of legacy transactions before berlin
+            r#type: i32::try_from(tx.transaction_type.unwrap_or_default())
                 .map_err(anyhow::Error::msg)?,
             nonce: tx.nonce.as_u64(),
             r: format!("{:#x}", tx.r),
@@ -451,24 +1047,46 @@ impl TryFrom<(evm::Transaction, evm::TransactionReceipt)> for Transaction {
             v: format!("{:#x}", tx.v),
             value: format!("{:#x}", tx.value),
             gas: format!("{:#x}", tx.gas),
-            gas_price: format!("{:#x}", tx.gas_price.context("no gas price")?),
-            max_fee_per_gas: tx
-                .max_fee_per_gas
-                .and_then(|val| Some(format!("{:#x}", val))),
-            max_priority_fee_per_gas: tx
-                .max_priority_fee_per_gas
-                .and_then(|val| Some(format!("{:#x}", val))),
+            // system transactions of l2 chains might come without gas prices
+            gas_price: format!("{:#x}", tx.gas_price.unwrap_or_default()),
+            max_fee_per_gas: tx.max_fee_per_gas.map(|val| format!("{:#x}", val)),
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas.map(|val| format!("{:#x}", val)),
             y_parity: None,
             cumulative_gas_used: format!("{:#x}", receipt.cumulative_gas_used),
-            effective_gas_price: format!(
-                "{:#x}",
-                receipt
-                    .effective_gas_price
-                    .context("no effective gas price")?
-            ),
+            effective_gas_price: format!("{:#x}", receipt.effective_gas_price.unwrap_or_default()),
             gas_used: format!("{:#x}", receipt.gas_used.context("no gas used")?),
-            status: i32::try_from(receipt.status.context("no status")?)
-                .map_err(anyhow::Error::msg)?,
+            status: receipt
+                .status
+                .map(|status| i32::try_from(status).map_err(anyhow::Error::msg))
+                .transpose()?,
+            state_root: receipt.root.map(|root| format!("{:?}", root)),
+            logs_bloom: Some(format!("{:?}", receipt.logs_bloom)),
+            access_list: tx.access_list.map(|access_list| {
+                access_list
+                    .0
+                    .into_iter()
+                    .map(|item| AccessListItem {
+                        address: format!("{:?}", item.address),
+                        storage_keys: item
+                            .storage_keys
+                            .into_iter()
+                            .map(|key| format!("{:?}", key))
+                            .collect(),
+                    })
+                    .collect()
+            }),
+            max_fee_per_blob_gas: max_fee_per_blob_gas.map(|val| format!("{:#x}", val)),
+            blob_versioned_hashes: blob_versioned_hashes.map(|hashes| {
+                hashes
+                    .into_iter()
+                    .map(|hash| format!("{:?}", hash))
+                    .collect()
+            }),
+            authorization_list,
+            l1_fee: l1_fee.map(|val| format!("{:#x}", val)),
+            l1_gas_price: l1_gas_price.map(|val| format!("{:#x}", val)),
+            l1_gas_used: l1_gas_used.map(|val| format!("{:#x}", val)),
+            l1_fee_scalar,
         })
     }
 }
@@ -477,79 +1095,164 @@ impl TryFrom<evm::Trace> for Trace {
     type Error = anyhow::Error;
 
     fn try_from(value: evm::Trace) -> Result<Self, Self::Error> {
+        let trace_address = value
+            .trace_address
+            .iter()
+            .map(|index| u32::try_from(*index))
+            .collect::<Result<Vec<_>, _>>()?;
         match &value.action {
-            evm::Action::Call(action) => {
-                Ok(Trace {
-                    transaction_index: value.transaction_position.context("no transaction position")?.try_into()?,
-                    r#type: TraceType::Call,
-                    error: value.error,
-                    revert_reason: None,
-                    action: Some(TraceAction {
-                        from: Some(format!("{:?}", action.from)),
-                        to: Some(format!("{:?}", action.to)),
-                        gas: Some(format!("{:#x}", action.gas)),
-                        input: Some(action.input.to_hex_prefixed()),
-                        r#type: match action.call_type {
-                            evm::CallType::None => None,
-                            evm::CallType::CallCode => Some(CallType::Callcode),
-                            evm::CallType::DelegateCall => Some(CallType::Delegatecall),
-                            evm::CallType::StaticCall => Some(CallType::Staticcall),
-                            evm::CallType::Call => Some(CallType::Call),
-                        },
-                        value: Some(format!("{:#x}", action.value)),
-                    }),
-                    result: value.result.and_then(|result| {
-                        if let evm::Res::Call(res) = &result {
-                            Some(TraceResult {
-                                gas_used: Some(format!("{:#x}", res.gas_used)),
-                                address: None,
-                                output: Some(res.output.to_hex_prefixed()),
-                            })
-                        } else {
-                            None
-                        }
-                    }),
-                })
-            }
-            evm::Action::Create(action) => {
+            evm::Action::Call(action) => Ok(Trace {
+                transaction_index: value
+                    .transaction_position
+                    .context("no transaction position")?
+                    .try_into()?,
+                trace_address,
+                r#type: TraceType::Call,
+                error: value.error,
+                revert_reason: None,
+                action: Some(TraceAction {
+                    from: Some(format!("{:?}", action.from)),
+                    to: Some(format!("{:?}", action.to)),
+                    gas: Some(format!("{:#x}", action.gas)),
+                    input: Some(action.input.to_hex_prefixed()),
+                    r#type: match action.call_type {
+                        evm::CallType::None => None,
+                        evm::CallType::CallCode => Some(CallType::Callcode),
+                        evm::CallType::DelegateCall => Some(CallType::Delegatecall),
+                        evm::CallType::StaticCall => Some(CallType::Staticcall),
+                        evm::CallType::Call => Some(CallType::Call),
+                    },
+                    value: Some(format!("{:#x}", action.value)),
+                    reward_type: None,
+                }),
+                result: value.result.and_then(|result| {
+                    if let evm::Res::Call(res) = &result {
+                        Some(TraceResult {
+                            gas_used: Some(format!("{:#x}", res.gas_used)),
+                            address: None,
+                            output: Some(res.output.to_hex_prefixed()),
+                        })
+                    } else {
+                        None
+                    }
+                }),
+            }),
+            evm::Action::Create(action) => Ok(Trace {
+                transaction_index: value
+                    .transaction_position
+                    .context("no transaction position")?
+                    .try_into()?,
+                trace_address,
+                r#type: TraceType::Create,
+                error: value.error,
+                revert_reason: None,
+                action: Some(TraceAction {
+                    from: Some(format!("{:?}", action.from)),
+                    value: Some(format!("{:#x}", action.value)),
+                    gas: Some(format!("{:#x}", action.gas)),
+                    to: None,
+                    input: Some(action.init.to_hex_prefixed()),
+                    r#type: None,
+                    reward_type: None,
+                }),
+                result: value.result.and_then(|result| {
+                    if let evm::Res::Create(res) = &result {
+                        Some(TraceResult {
+                            gas_used: Some(format!("{:#x}", res.gas_used)),
+                            address: Some(format!("{:?}", res.address)),
+                            output: Some(res.code.to_hex_prefixed()),
+                        })
+                    } else {
+                        None
+                    }
+                }),
+            }),
+            evm::Action::Suicide(action) => Ok(Trace {
+                transaction_index: value
+                    .transaction_position
+                    .context("no transaction position")?
+                    .try_into()?,
+                trace_address,
+                r#type: TraceType::Suicide,
+                error: value.error,
+                revert_reason: None,
+                action: Some(TraceAction {
+                    from: Some(format!("{:?}", action.address)),
+                    to: Some(format!("{:?}", action.refund_address)),
+                    value: Some(format!("{:#x}", action.balance)),
+                    gas: None,
+                    input: None,
+                    r#type: None,
+                    reward_type: None,
+                }),
+                result: None,
+            }),
+            evm::Action::Reward(action) => {
                 Ok(Trace {
-                    transaction_index: value.transaction_position.context("no transaction position")?.try_into()?,
-                    r#type: TraceType::Call,
+                    transaction_index: 0, // rewards don't belong to any transaction
+                    trace_address,
+                    r#type: TraceType::Reward,
                     error: value.error,
                     revert_reason: None,
                     action: Some(TraceAction {
-                        from: Some(format!("{:?}", action.from)),
+                        from: None,
+                        to: Some(format!("{:?}", action.author)),
                         value: Some(format!("{:#x}", action.value)),
-                        gas: Some(format!("{:#x}", action.gas)),
-                        to: None,
+                        gas: None,
                         input: None,
                         r#type: None,
+                        reward_type: Some(match action.reward_type {
+                            evm::RewardType::Block => RewardType::Block,
+                            evm::RewardType::Uncle => RewardType::Uncle,
+                            evm::RewardType::EmptyStep => RewardType::EmptyStep,
+                            evm::RewardType::External => RewardType::External,
+                        }),
                     }),
-                    result: value.result.and_then(|result| {
-                        if let evm::Res::Create(res) = &result {
-                            Some(TraceResult {
-                                gas_used: Some(format!("{:#x}", res.gas_used)),
-                                address: Some(format!("{:?}", res.address)),
-                                output: None,
-                            })
-                        } else {
-                            None
-                        }
-                    }),
+                    result: None,
                 })
-            },
-            _ => anyhow::bail!("only call and create traces are supported")
+            }
         }
     }
 }
 
-fn split_range(from: u64, to: u64) -> Vec<Range> {
+pub(crate) async fn get_block_hash(client: &Provider<Http>, height: u64) -> anyhow::Result<String> {
+    let hash = retry(|| async {
+        let block = client
+            .get_block(height)
+            .await?
+            .ok_or(ConsistencyError::BlockNotFound(height.into()))?;
+        Ok(block
+            .hash
+            .ok_or(ConsistencyError::MissingField("block hash"))?)
+    })
+    .await?;
+    Ok(format!("{:?}", hash))
+}
+
+async fn get_balances(
+    client: &Provider<Http>,
+    height: u64,
+    addresses: Vec<String>,
+) -> anyhow::Result<HashMap<String, String>> {
+    let futures: Vec<_> = addresses
+        .into_iter()
+        .map(|address| async move {
+            let account: evm::Address = address.parse()?;
+            let balance =
+                retry(|| async { Ok(client.get_balance(account, Some(height.into())).await?) })
+                    .await?;
+            Ok::<_, anyhow::Error>((address, format!("{:#x}", balance)))
+        })
+        .collect();
+    join_all(futures).await.into_iter().collect()
+}
+
+pub(crate) fn split_range(from: u64, to: u64, stride: u64) -> Vec<Range> {
     assert!(from <= to);
-    let step = 100;
     let mut from = from;
     let mut ranges = vec![];
     while from <= to {
-        let to_ = min(from + step - 1, to);
+        let to_ = min(from + stride - 1, to);
         ranges.push((from, to_));
         from = to_ + 1;
     }
@@ -559,7 +1262,9 @@ fn split_range(from: u64, to: u64) -> Vec<Range> {
 pub struct RpcDataSource {
     client: Provider<Http>,
     height_tracker: Arc<HeightTracker>,
-    finality_confirmation: u64,
+    stride_hints: Arc<StrideHints>,
+    follower: Arc<OnceCell<Arc<ChainFollower>>>,
+    max_reorg_depth: u64,
 }
 
 #[async_trait::async_trait]
@@ -570,11 +1275,11 @@ impl DataSource for RpcDataSource {
         _stop_on_head: bool,
     ) -> anyhow::Result<BlockStream> {
         let client = self.client.clone();
-        let finality_confirmation = self.finality_confirmation;
         let height_tracker = self.height_tracker.clone();
+        let stride_hints = self.stride_hints.clone();
 
         Ok(Box::new(try_stream! {
-            let height = get_finalized_height(&height_tracker, finality_confirmation).await?;
+            let height = height_tracker.heights().await?.finalized;
             let to = if let Some(to) = request.to {
                 min(height, to)
             } else {
@@ -585,9 +1290,15 @@ impl DataSource for RpcDataSource {
                 return
             }
 
-            let ranges = split_range(request.from, to);
-            for chunk in ranges.chunks(5) {
-                let futures: Vec<_> = chunk.into_iter().map(|range| get_stride(&client, range, &request)).collect();
+            let mut from = request.from;
+            while from <= to {
+                let batch_to = min(from + MAX_STRIDE_SIZE - 1, to);
+                let stride = stride_hints.range_size(&request)?;
+                let ranges = split_range(from, batch_to, stride);
+                let futures: Vec<_> = ranges
+                    .iter()
+                    .map(|range| retry(|| get_stride(&client, range, &request, &stride_hints)))
+                    .collect();
                 let results = join_all(futures).await;
 
                 let mut blocks = vec![];
@@ -595,24 +1306,27 @@ impl DataSource for RpcDataSource {
                     let mut stride_blocks = result?;
                     blocks.append(&mut stride_blocks);
                 }
+                from = batch_to + 1;
                 yield blocks;
             }
         }))
     }
 
     async fn get_finalized_height(&self) -> anyhow::Result<u64> {
-        let height = get_finalized_height(&self.height_tracker, self.finality_confirmation).await?;
-        Ok(height)
+        let heights = self.height_tracker.heights().await?;
+        Ok(heights.finalized)
     }
 
     async fn get_block_hash(&self, height: u64) -> anyhow::Result<String> {
-        let block = self
-            .client
-            .get_block(height)
-            .await?
-            .context(format!("block №{} not found", height))?;
-        let hash = format!("{:?}", block.hash.context("hash is empty")?);
-        Ok(hash)
+        get_block_hash(&self.client, height).await
+    }
+
+    async fn get_balances(
+        &self,
+        height: u64,
+        addresses: Vec<String>,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        get_balances(&self.client, height, addresses).await
     }
 }
 
@@ -624,56 +1338,18 @@ impl HotSource for RpcDataSource {
         state: HashAndHeight,
     ) -> anyhow::Result<HotBlockStream> {
         let client = self.client.clone();
-        let finality_confirmation = self.finality_confirmation;
         let height_tracker = self.height_tracker.clone();
+        let stride_hints = self.stride_hints.clone();
+        let follower = self.follower.clone();
+        let max_reorg_depth = self.max_reorg_depth;
         Ok(Box::new(try_stream! {
-            let mut nav = ForkNavigator::new(state, |block_id| {
-                let client = client.clone();
-                let request = request.clone();
-                async move {
-                    let rpc_block = client.get_block_with_txs(block_id).await?
-                        .ok_or(anyhow::anyhow!("consistency error"))?;
-                    let mut blocks = get_requested_data(&client, vec![rpc_block], &request).await?;
-                    let block = blocks.remove(0);
-                    Ok(block)
-                }
-            });
-
-            for await result in get_height_updates(height_tracker, request.from) {
-                let top = result?;
-                let finalized = top.saturating_sub(finality_confirmation);
-                let height = nav.get_height();
-
-                for number in height + 1..top {
-                    let mut retries = 0;
-                    let update = loop {
-                        match nav.r#move(number, min(number, finalized)).await {
-                            Ok(update) => break update,
-                            Err(err) => {
-                                match err.downcast::<&str>() {
-                                    Ok(err_msg) => {
-                                        if err_msg == "consistency error" && retries < 10 {
-                                            retries += 1;
-                                            let duration = Duration::from_millis(200 * retries);
-                                            tokio::time::sleep(duration).await;
-                                            continue;
-                                        }
-                                    },
-                                    Err(err) => Err(err)?,
-                                };
-                            }
-                        }
-                    };
-                    let finalized_head = update.finalized_head.height;
-
-                    yield update;
+            let follower = follower
+                .get_or_try_init(|| ChainFollower::start(client.clone(), height_tracker, max_reorg_depth))
+                .await?
+                .clone();
 
-                    if let Some(to) = request.to {
-                        if finalized_head >= to {
-                            return
-                        }
-                    }
-                }
+            for await update in follower::get_hot_blocks(follower, client, stride_hints, request, state) {
+                yield update?;
             }
         }))
     }
@@ -686,151 +1362,62 @@ impl HotSource for RpcDataSource {
 impl HotDataSource for RpcDataSource {}
 
 impl RpcDataSource {
-    pub fn new(url: String, finality_confirmation: u64) -> RpcDataSource {
+    pub fn new(url: String, finality: Finality, max_reorg_depth: u64) -> RpcDataSource {
         let client = Provider::<Http>::try_from(url).unwrap();
-        let height_tracker = Arc::new(HeightTracker::new(client.clone(), Duration::from_secs(1)));
+        let height_tracker = Arc::new(HeightTracker::new(
+            client.clone(),
+            finality,
+            Duration::from_secs(1),
+        ));
         RpcDataSource {
             client,
             height_tracker,
-            finality_confirmation,
+            stride_hints: Arc::new(StrideHints::default()),
+            follower: Arc::new(OnceCell::new()),
+            max_reorg_depth,
         }
     }
 }
 
-fn get_height_updates(
-    height_tracker: Arc<HeightTracker>,
-    from: u64,
-) -> impl Stream<Item = anyhow::Result<u64>> {
-    try_stream! {
-        let mut from = from;
-        loop {
-            from = height_tracker.wait(from).await?;
-            yield from;
-            from += 1;
-        }
-    }
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Heights {
+    pub head: u64,
+    pub finalized: u64,
 }
 
-struct ForkNavigator<C, F>
-where
-    C: Fn(evm::BlockId) -> F,
-    F: Future<Output = anyhow::Result<Block>>,
-{
-    chain: Vec<HashAndHeight>,
-    get_block: C,
-}
-
-impl<C, F> ForkNavigator<C, F>
-where
-    C: Fn(evm::BlockId) -> F,
-    F: Future<Output = anyhow::Result<Block>>,
-{
-    pub fn new(state: HashAndHeight, get_block: C) -> ForkNavigator<C, F> {
-        ForkNavigator {
-            chain: vec![state],
-            get_block,
+async fn get_heights(client: &Provider<Http>, finality: Finality) -> anyhow::Result<Heights> {
+    let head = client.get_block_number().await?.as_u64();
+    let finalized = match finality {
+        Finality::Confirmations(confirmations) => head.saturating_sub(confirmations),
+        Finality::Tag(tag) => {
+            let block = client
+                .get_block(evm::BlockNumber::from(tag))
+                .await?
+                .with_context(|| format!("{:?} block not found", tag))?;
+            let number = block.number.context("no number")?.as_u64();
+            // the tag could be resolved against a newer head than the one fetched above
+            min(number, head)
         }
-    }
-
-    pub fn get_height(&self) -> u64 {
-        self.chain
-            .last()
-            .expect("chain state can't be empty")
-            .height
-    }
-
-    pub async fn r#move(&mut self, best: u64, finalized: u64) -> anyhow::Result<HotUpdate> {
-        let mut chain = self.chain.clone();
-        let mut new_blocks = vec![];
-
-        let best_head = if best > chain.last().unwrap().height {
-            let new_block = (self.get_block)(best.into()).await?;
-            let best_head = HashAndHeight {
-                hash: new_block.header.parent_hash.clone(),
-                height: new_block.header.number - 1,
-            };
-            new_blocks.push(new_block);
-            Some(best_head)
-        } else {
-            None
-        };
-
-        if let Some(mut best_head) = best_head {
-            while chain.last().unwrap().height < best_head.height {
-                let hash = best_head.hash.parse::<evm::H256>()?;
-                let block = (self.get_block)(hash.into()).await?;
-                best_head = HashAndHeight {
-                    hash: block.header.parent_hash.clone(),
-                    height: block.header.number - 1,
-                };
-                new_blocks.push(block);
-            }
-
-            while chain.last().unwrap().hash != best_head.hash {
-                let hash = best_head.hash.parse::<evm::H256>()?;
-                let block = (self.get_block)(hash.into()).await?;
-                best_head = HashAndHeight {
-                    hash: block.header.parent_hash.clone(),
-                    height: block.header.number - 1,
-                };
-                new_blocks.push(block);
-                chain.pop();
-            }
-        }
-
-        new_blocks.reverse();
-        for block in &new_blocks {
-            chain.push(HashAndHeight {
-                hash: block.header.hash.clone(),
-                height: block.header.number,
-            });
-        }
-
-        let finalized_head = if finalized > chain[0].height {
-            let finalized_pos = usize::try_from(finalized - chain[0].height)?;
-            Some(chain[finalized_pos].clone())
-        } else {
-            None
-        };
-
-        if let Some(finalized_head) = finalized_head {
-            if finalized_head.height >= chain[0].height {
-                let finalized_pos = usize::try_from(finalized_head.height - chain[0].height)?;
-                chain = chain[finalized_pos..].to_vec();
-            }
-        }
-
-        self.chain = chain;
-
-        let base_head = if new_blocks.is_empty() {
-            self.chain.last().unwrap().clone()
-        } else {
-            HashAndHeight {
-                height: new_blocks[0].header.number - 1,
-                hash: new_blocks[0].header.parent_hash.clone(),
-            }
-        };
-
-        Ok(HotUpdate {
-            blocks: new_blocks,
-            base_head,
-            finalized_head: self.chain[0].clone(),
-        })
-    }
+    };
+    Ok(Heights { head, finalized })
 }
 
-struct HeightTracker {
-    tx: mpsc::UnboundedSender<(u128, oneshot::Sender<anyhow::Result<u64>>)>,
+pub(crate) struct HeightTracker {
+    tx: mpsc::UnboundedSender<(u128, oneshot::Sender<anyhow::Result<Heights>>)>,
     interval: Duration,
 }
 
 impl HeightTracker {
-    fn new(client: Provider<Http>, interval: Duration) -> HeightTracker {
+    pub(crate) fn new(
+        client: Provider<Http>,
+        finality: Finality,
+        interval: Duration,
+    ) -> HeightTracker {
         let (tx, mut rx) =
-            mpsc::unbounded_channel::<(u128, oneshot::Sender<anyhow::Result<u64>>)>();
+            mpsc::unbounded_channel::<(u128, oneshot::Sender<anyhow::Result<Heights>>)>();
 
         tokio::spawn(async move {
-            let mut last_height = 0;
+            let mut last_heights = Heights::default();
             let mut last_access = 0;
             let interval = interval.as_millis();
 
@@ -838,45 +1425,220 @@ impl HeightTracker {
                 let diff = time.saturating_sub(last_access);
 
                 if diff > interval {
-                    last_height = match client.get_block_number().await {
-                        Ok(number) => number.as_u64(),
+                    last_heights = match get_heights(&client, finality).await {
+                        Ok(heights) => heights,
                         Err(e) => {
-                            tx.send(Err(e.into())).expect("the receiver dropped");
+                            // the receiver could have been dropped along with a closed stream
+                            let _ = tx.send(Err(e));
                             continue;
                         }
                     };
                     last_access = match SystemTime::now().duration_since(UNIX_EPOCH) {
                         Ok(now) => now.as_millis(),
                         Err(e) => {
-                            tx.send(Err(e.into())).expect("the receiver dropped");
+                            let _ = tx.send(Err(e.into()));
                             continue;
                         }
                     };
                 }
 
-                tx.send(Ok(last_height)).expect("the receiver dropped");
+                let _ = tx.send(Ok(last_heights));
             }
         });
 
         HeightTracker { tx, interval }
     }
 
-    async fn height(&self) -> anyhow::Result<u64> {
+    pub async fn heights(&self) -> anyhow::Result<Heights> {
         let now = SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)?
             .as_millis();
         let (tx, rx) = oneshot::channel();
-        self.tx.send((now, tx)).expect("the receiver dropped");
-        let height = rx.await.expect("the sender dropped")?;
-        Ok(height)
+        self.tx
+            .send((now, tx))
+            .map_err(|_| anyhow::anyhow!("height tracker has stopped"))?;
+        let heights = rx
+            .await
+            .context("height tracker has dropped the request")??;
+        Ok(heights)
     }
 
-    async fn wait(&self, height: u64) -> anyhow::Result<u64> {
-        let mut current = self.height().await?;
-        while current < height {
+    pub async fn wait(&self, height: u64) -> anyhow::Result<Heights> {
+        let mut current = self.heights().await?;
+        while current.head < height {
             tokio::time::sleep(self.interval).await;
-            current = self.height().await?;
+            current = self.heights().await?;
         }
         Ok(current)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_replays, is_range_limit_error, is_requested_call, log_key, query_adaptive, retry,
+        split_range, ConsistencyError, StrideHints,
+    };
+    use crate::datasource::{DataRequest, LogRequest};
+    use ethers_core::types as evm;
+    use ethers_providers::ProviderError;
+    use std::sync::Mutex;
+
+    #[test]
+    fn split_range_into_strides() {
+        assert_eq!(split_range(0, 99, 100), vec![(0, 99)]);
+        assert_eq!(
+            split_range(10, 250, 100),
+            vec![(10, 109), (110, 209), (210, 250)]
+        );
+    }
+
+    #[test]
+    fn match_calls_against_filters() {
+        let to = "0x00000000000000000000000000000000000000aa".to_string();
+        let input = "0xa9059cbb0000";
+        let address = vec![to.clone()];
+        let sighash = vec!["0xa9059cbb".to_string()];
+
+        assert!(is_requested_call(Some(&to), input, &address, &sighash));
+        assert!(!is_requested_call(None, input, &address, &sighash));
+        assert!(!is_requested_call(Some(&to), "0x", &address, &sighash));
+        // one empty list matches nothing
+        assert!(!is_requested_call(Some(&to), input, &address, &[]));
+        assert!(!is_requested_call(Some(&to), input, &[], &sighash));
+        // both empty lists request all calls
+        assert!(is_requested_call(None, "0x", &[], &[]));
+    }
+
+    #[tokio::test]
+    async fn bisect_range_on_limit_errors() {
+        let hints = StrideHints::default();
+        let calls = Mutex::new(vec![]);
+
+        let blocks = query_adaptive(&(0, 99), &hints, "logs", |(from, to)| {
+            calls.lock().unwrap().push((from, to));
+            async move {
+                if to - from + 1 > 25 {
+                    Err(ProviderError::CustomError(
+                        "query returned more than 10000 results".to_string(),
+                    ))
+                } else {
+                    Ok((from..=to).collect::<Vec<_>>())
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(blocks, (0..=99).collect::<Vec<_>>());
+        assert_eq!(calls.lock().unwrap()[..3], [(0, 99), (0, 49), (0, 24)]);
+        assert_eq!(hints.get("logs"), 50);
+
+        let blocks = query_adaptive(&(100, 299), &hints, "logs", |(from, to)| async move {
+            Ok::<_, ProviderError>((from..=to).collect::<Vec<_>>())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(blocks.len(), 200);
+        assert_eq!(hints.get("logs"), 100);
+    }
+
+    #[tokio::test]
+    async fn grow_ranges_of_sparse_filters() {
+        let hints = StrideHints::default();
+        let request = DataRequest {
+            from: 0,
+            to: None,
+            logs: vec![LogRequest::default()],
+            transactions: vec![],
+            traces: vec![],
+            uncles: false,
+        };
+        assert_eq!(hints.range_size(&request).unwrap(), 100);
+
+        let key = log_key(&request.logs[0]);
+        query_adaptive(&(0, 4999), &hints, &key, |(from, to)| async move {
+            Ok::<_, ProviderError>((from..=to).collect::<Vec<_>>())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(hints.get(&key), 500);
+        assert_eq!(hints.range_size(&request).unwrap(), 500);
+
+        // a filter whose stride was bisected keeps the default range size
+        hints.shrink(&key, 100);
+        assert_eq!(hints.range_size(&request).unwrap(), 100);
+    }
+
+    #[test]
+    fn match_only_range_limit_errors() {
+        let error = |message: &str| ProviderError::CustomError(message.to_string());
+        assert!(is_range_limit_error(&error(
+            "query returned more than 10000 results"
+        )));
+        assert!(is_range_limit_error(&error("Log response size exceeded.")));
+        assert!(!is_range_limit_error(&error("invalid block range")));
+    }
+
+    #[tokio::test]
+    async fn fail_on_other_errors() {
+        let hints = StrideHints::default();
+        let result = query_adaptive(&(0, 99), &hints, "logs", |_| async {
+            Err::<Vec<u64>, _>(ProviderError::CustomError("connection reset".to_string()))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(hints.get("logs"), 100);
+    }
+
+    #[tokio::test]
+    async fn retry_inconsistent_responses() {
+        let attempts = Mutex::new(0);
+        let result = retry(|| async {
+            let mut attempts = attempts.lock().unwrap();
+            *attempts += 1;
+            if *attempts < 3 {
+                Err(ConsistencyError::BlockNotFound(1.into()))?;
+            }
+            Ok(*attempts)
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+
+        let attempts = Mutex::new(0);
+        let result = retry(|| async {
+            *attempts.lock().unwrap() += 1;
+            Err::<(), _>(anyhow::anyhow!("invalid params"))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(*attempts.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn detect_foreign_replays() {
+        let block = evm::H256::from_low_u64_be(100);
+        let transactions = [evm::H256::from_low_u64_be(1), evm::H256::from_low_u64_be(2)];
+        let replay = |hash: &evm::H256| -> evm::BlockTrace {
+            serde_json::from_value(serde_json::json!({"output": "0x", "transactionHash": hash}))
+                .unwrap()
+        };
+
+        let replays: Vec<_> = transactions.iter().map(replay).collect();
+        assert!(check_replays(block, &transactions, &replays).is_ok());
+
+        // the node has replayed the block of another fork at the same height
+        let replays = vec![
+            replay(&transactions[0]),
+            replay(&evm::H256::from_low_u64_be(3)),
+        ];
+        assert!(matches!(
+            check_replays(block, &transactions, &replays),
+            Err(ConsistencyError::ForeignReplays(hash)) if hash == block
+        ));
+        assert!(check_replays(block, &transactions, &replays[..1]).is_err());
+    }
+}
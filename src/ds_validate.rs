@@ -0,0 +1,350 @@
+use crate::datasource::{
+    Block, BlockStream, DataRequest, DataSource, HashAndHeight, HotBlockStream, HotDataSource, HotSource,
+};
+use crate::metrics::INVALID_BLOCKS_COUNTER;
+use crate::verify::verify_header_hash;
+use async_stream::try_stream;
+use futures_util::stream::StreamExt;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use tracing::warn;
+
+// number of times in a row a range is queried again before the stream fails
+const MAX_REQUERIES: u32 = 3;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ValidationMode {
+    /// Fail the stream on the first invalid block
+    Fail,
+    /// Query the data source again starting right after the last valid block,
+    /// parent hash mismatches still fail the stream since the last valid block is the one in doubt
+    Requery,
+}
+
+#[derive(Debug)]
+pub enum ValidationError {
+    Gap { expected: u64, got: u64 },
+    Duplicate(u64),
+    NotMonotonic { prev: u64, got: u64 },
+    ParentMismatch { number: u64, parent_hash: String, expected: String },
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::Gap { expected, got } => {
+                write!(f, "expected block {} but got {}", expected, got)
+            }
+            ValidationError::Duplicate(number) => write!(f, "block {} is duplicated", number),
+            ValidationError::NotMonotonic { prev, got } => {
+                write!(f, "block {} goes after block {}", got, prev)
+            }
+            ValidationError::ParentMismatch { number, parent_hash, expected } => write!(
+                f,
+                "block {} has parent {} but the previous block is {}",
+                number, parent_hash, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl ValidationError {
+    // a requery starts after the last valid block so it can't replace that block
+    fn is_requeryable(&self) -> bool {
+        !matches!(self, ValidationError::ParentMismatch { .. })
+    }
+}
+
+/// Checks that a block correctly continues the stream.
+/// Every block is expected if the request has no filters. Filtered streams skip
+/// blocks without matching data, so gaps can't be detected for them and parent
+/// hashes are only compared between adjacent blocks.
+fn validate(
+    last: Option<&HashAndHeight>,
+    from: u64,
+    dense: bool,
+    block: &Block,
+) -> Result<(), ValidationError> {
+    let number = block.header.number;
+    let last = match last {
+        Some(last) => last,
+        None if dense && number != from => {
+            return Err(ValidationError::Gap { expected: from, got: number })
+        }
+        None => return Ok(()),
+    };
+
+    if number == last.height {
+        return Err(ValidationError::Duplicate(number));
+    }
+    if number < last.height {
+        return Err(ValidationError::NotMonotonic { prev: last.height, got: number });
+    }
+    if dense && number != last.height + 1 {
+        return Err(ValidationError::Gap { expected: last.height + 1, got: number });
+    }
+    if number == last.height + 1 && block.header.parent_hash != last.hash {
+        return Err(ValidationError::ParentMismatch {
+            number,
+            parent_hash: block.header.parent_hash.clone(),
+            expected: last.hash.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Data source wrapper which checks numbering and parent hashes of finalized blocks
+/// and optionally verifies that header hashes match their content.
+/// Hot blocks are passed through as they are since reorgs are handled by the hot source itself.
+pub struct ValidatedDataSource<T: ?Sized> {
+    inner: Arc<T>,
+    mode: ValidationMode,
+    verify_hashes: bool,
+}
+
+impl<T: ?Sized> ValidatedDataSource<T> {
+    pub fn new(inner: Arc<T>, mode: ValidationMode, verify_hashes: bool) -> ValidatedDataSource<T> {
+        ValidatedDataSource { inner, mode, verify_hashes }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: DataSource + Send + Sync + ?Sized + 'static> DataSource for ValidatedDataSource<T> {
+    async fn get_finalized_blocks(
+        &self,
+        request: DataRequest,
+        stop_on_head: bool,
+    ) -> anyhow::Result<BlockStream> {
+        let inner = self.inner.clone();
+        let mode = self.mode;
+        let verify_hashes = self.verify_hashes;
+        let dense = request.logs.is_empty() && request.transactions.is_empty() && request.traces.is_empty();
+
+        Ok(Box::new(try_stream! {
+            let mut last: Option<HashAndHeight> = None;
+            let mut requeries = 0;
+            'outer: loop {
+                let mut req = request.clone();
+                if let Some(last) = &last {
+                    req.from = last.height + 1;
+                }
+
+                let mut stream = Pin::from(inner.get_finalized_blocks(req, stop_on_head).await?);
+                while let Some(result) = stream.next().await {
+                    let mut valid = vec![];
+                    let mut error = None;
+                    for block in result? {
+                        let result = validate(last.as_ref(), request.from, dense, &block)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|_| {
+                                if verify_hashes {
+                                    verify_header_hash(&block.header)
+                                } else {
+                                    Ok(())
+                                }
+                            });
+                        match result {
+                            Ok(()) => {
+                                last = Some((&block).into());
+                                valid.push(block);
+                            }
+                            Err(err) => {
+                                error = Some(err);
+                                break;
+                            }
+                        }
+                    }
+
+                    if !valid.is_empty() {
+                        requeries = 0;
+                        yield valid;
+                    }
+
+                    if let Some(err) = error {
+                        INVALID_BLOCKS_COUNTER.inc();
+                        let requeryable = err
+                            .downcast_ref::<ValidationError>()
+                            .map_or(true, ValidationError::is_requeryable);
+                        match mode {
+                            ValidationMode::Requery if requeryable && requeries < MAX_REQUERIES => {
+                                requeries += 1;
+                                warn!("invalid block stream, querying again: {}", err);
+                                continue 'outer;
+                            }
+                            _ => Err(err)?,
+                        }
+                    }
+                }
+                break;
+            }
+        }))
+    }
+
+    async fn get_finalized_height(&self) -> anyhow::Result<u64> {
+        self.inner.get_finalized_height().await
+    }
+
+    async fn get_block_hash(&self, height: u64) -> anyhow::Result<String> {
+        self.inner.get_block_hash(height).await
+    }
+
+    async fn get_balances(
+        &self,
+        height: u64,
+        addresses: Vec<String>,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        self.inner.get_balances(height, addresses).await
+    }
+}
+
+impl<T: HotDataSource + Send + Sync + ?Sized + 'static> HotSource for ValidatedDataSource<T> {
+    fn get_hot_blocks(
+        &self,
+        request: DataRequest,
+        state: HashAndHeight,
+    ) -> anyhow::Result<HotBlockStream> {
+        self.inner.get_hot_blocks(request, state)
+    }
+
+    fn as_ds(&self) -> &(dyn DataSource + Send + Sync) {
+        self
+    }
+}
+
+impl<T: HotDataSource + Send + Sync + ?Sized + 'static> HotDataSource for ValidatedDataSource<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{ValidatedDataSource, ValidationError, ValidationMode};
+    use crate::datasource::{test_block, Block, BlockStream, DataRequest, DataSource};
+    use futures_util::stream::{self, StreamExt};
+    use std::collections::HashMap;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+
+    fn block(number: u64) -> Block {
+        test_block(number, format!("{:#x}", number + 1), format!("{:#x}", number))
+    }
+
+    // replays the block 2 after a restart on the first query and optionally forks at block 3
+    #[derive(Default)]
+    struct ReplayingDataSource {
+        queries: Mutex<Vec<u64>>,
+        forked: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl DataSource for ReplayingDataSource {
+        async fn get_finalized_blocks(
+            &self,
+            request: DataRequest,
+            _stop_on_head: bool,
+        ) -> anyhow::Result<BlockStream> {
+            let mut queries = self.queries.lock().unwrap();
+            let numbers = if queries.is_empty() {
+                vec![vec![0, 1, 2], vec![2, 3]]
+            } else {
+                vec![(request.from..=request.to.unwrap()).collect()]
+            };
+            queries.push(request.from);
+            let forked = self.forked;
+            let batches = numbers.into_iter().map(|batch| {
+                Ok(batch
+                    .into_iter()
+                    .map(|number| {
+                        let mut block = block(number);
+                        // block 3 is built on top of another block 2
+                        if forked && number == 3 {
+                            block.header.parent_hash = "0xff".to_string();
+                        }
+                        block
+                    })
+                    .collect())
+            });
+            Ok(Box::new(stream::iter(batches.collect::<Vec<_>>())))
+        }
+
+        async fn get_finalized_height(&self) -> anyhow::Result<u64> {
+            Ok(5)
+        }
+
+        async fn get_block_hash(&self, height: u64) -> anyhow::Result<String> {
+            Ok(format!("{:#x}", height + 1))
+        }
+
+        async fn get_balances(
+            &self,
+            _height: u64,
+            _addresses: Vec<String>,
+        ) -> anyhow::Result<HashMap<String, String>> {
+            Ok(HashMap::new())
+        }
+    }
+
+    fn request() -> DataRequest {
+        DataRequest {
+            from: 0,
+            to: Some(5),
+            logs: vec![],
+            transactions: vec![],
+            traces: vec![],
+            uncles: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn requery_replayed_blocks() {
+        let inner = Arc::new(ReplayingDataSource::default());
+        let ds = ValidatedDataSource::new(inner.clone(), ValidationMode::Requery, false);
+
+        let mut stream = Pin::from(ds.get_finalized_blocks(request(), true).await.unwrap());
+        let mut numbers = vec![];
+        while let Some(blocks) = stream.next().await {
+            numbers.extend(blocks.unwrap().iter().map(|block| block.header.number));
+        }
+
+        assert_eq!(numbers, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(*inner.queries.lock().unwrap(), vec![0, 3]);
+    }
+
+    #[tokio::test]
+    async fn fail_on_replayed_blocks() {
+        let inner = Arc::new(ReplayingDataSource::default());
+        let ds = ValidatedDataSource::new(inner, ValidationMode::Fail, false);
+
+        let mut stream = Pin::from(ds.get_finalized_blocks(request(), true).await.unwrap());
+        assert_eq!(stream.next().await.unwrap().unwrap().len(), 3);
+        let err = stream.next().await.unwrap().err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<ValidationError>(),
+            Some(ValidationError::Duplicate(2))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fail_on_parent_mismatch_in_requery_mode() {
+        let inner = Arc::new(ReplayingDataSource { forked: true, ..Default::default() });
+        let ds = ValidatedDataSource::new(inner.clone(), ValidationMode::Requery, false);
+
+        let mut stream = Pin::from(ds.get_finalized_blocks(request(), true).await.unwrap());
+        let mut numbers = vec![];
+        let mut err = None;
+        while let Some(result) = stream.next().await {
+            match result {
+                Ok(blocks) => numbers.extend(blocks.iter().map(|block| block.header.number)),
+                Err(e) => err = Some(e),
+            }
+        }
+
+        // the replay of block 2 is requeried but the fork at block 3 isn't
+        assert_eq!(numbers, vec![0, 1, 2]);
+        assert_eq!(*inner.queries.lock().unwrap(), vec![0, 3]);
+        assert!(matches!(
+            err.unwrap().downcast_ref::<ValidationError>(),
+            Some(ValidationError::ParentMismatch { number: 3, .. })
+        ));
+    }
+}
@@ -4,6 +4,7 @@ use tracing::error;
 
 use crate::firehose::Firehose;
 use crate::pbfirehose::{fetch_server::Fetch, SingleBlockRequest, SingleBlockResponse};
+use crate::stream::to_status;
 
 pub struct PortalFetch {
     firehose: Arc<Firehose>,
@@ -26,7 +27,7 @@ impl Fetch for PortalFetch {
             Ok(response) => response,
             Err(e) => {
                 error!("failed to fetch block: {}", e);
-                return Err(tonic::Status::unavailable("operation failed"));
+                return Err(to_status(&e));
             }
         };
 
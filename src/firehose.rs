@@ -1,21 +1,42 @@
 use crate::cursor::Cursor;
 use crate::datasource::{
     Block, BlockHeader, CallType, DataRequest, DataSource, HashAndHeight, HotDataSource, Log,
-    LogRequest, Trace, TraceResult, TraceType, Transaction, TraceRequest, TxRequest,
+    LogRequest, RewardType, StateDiff, StateDiffKind, Trace, TraceResult, TraceType, Transaction,
+    TraceRequest, TxRequest, Withdrawal, EMPTY_UNCLES_HASH,
 };
+use crate::metrics::{HANDOFF_MISMATCHES_COUNTER, HANDOFF_UNDOS_COUNTER, SENDER_MISMATCHES_COUNTER};
 use crate::pbcodec;
+use crate::verify::{self, verify_block_roots};
 use crate::pbfirehose::single_block_request::Reference;
 use crate::pbfirehose::{ForkStep, Request, Response, SingleBlockRequest, SingleBlockResponse};
 use crate::pbtransforms::{CombinedFilter, CallToFilter, LogFilter};
 use anyhow::{format_err, Context};
 use async_stream::try_stream;
+use ethers_core::abi;
+use ethers_core::types::U256;
+use ethers_core::utils::keccak256;
 use futures_core::stream::Stream;
 use futures_util::stream::StreamExt;
 use prost::Message;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::cmp::max;
+use std::time::Duration;
+use tracing::warn;
+
+// number of times the rpc node is asked again before the last emitted block is considered to be on another chain
+const HANDOFF_RETRIES: u64 = 3;
+
+// blob gas used by each blob of a transaction (EIP-4844)
+const GAS_PER_BLOB: u64 = 131072;
+
+// selectors of the Error(string) and Panic(uint256) revert encodings
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+// type of bor state sync transactions since they became typed ones (PIP-74)
+const BOR_STATE_SYNC_TX_TYPE: i32 = 0x7f;
 
 async fn resolve_negative_start(
     start_block_num: i64,
@@ -34,6 +55,9 @@ async fn resolve_negative_start(
 }
 
 fn try_decode_hex(label: &'static str, value: &str) -> anyhow::Result<Vec<u8>> {
+    if value.is_empty() || value == "0x" {
+        return Ok(vec![]);
+    }
     let buf: Vec<u8> = if value.len() % 2 != 0 {
         let value = format!("0x0{}", &value[2..]);
         prefix_hex::decode(&value).map_err(|_| format_err!("invalid {}: {}", label, value))?
@@ -44,10 +68,322 @@ fn try_decode_hex(label: &'static str, value: &str) -> anyhow::Result<Vec<u8>> {
     Ok(buf)
 }
 
-fn qty2int(value: &String) -> anyhow::Result<u64> {
+fn qty2int(value: &str) -> anyhow::Result<u64> {
     Ok(u64::from_str_radix(value.trim_start_matches("0x"), 16)?)
 }
 
+#[derive(Debug)]
+pub enum HandoffError {
+    /// None of the emitted blocks within the reorg depth is a part of the next data source chain
+    NoCommonBlock { head: HashAndHeight, max_depth: u64 },
+    /// The first block of the next data source doesn't continue the emitted chain
+    ParentMismatch { block: HashAndHeight, parent: HashAndHeight },
+}
+
+impl std::fmt::Display for HandoffError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandoffError::NoCommonBlock { head, max_depth } => write!(
+                f,
+                "no block within {} blocks of the last emitted block {}#{} is a part of the next data source chain",
+                max_depth, head.height, head.hash
+            ),
+            HandoffError::ParentMismatch { block, parent } => write!(
+                f,
+                "block {}#{} isn't a child of the emitted block {}#{}",
+                block.height, block.hash, parent.height, parent.hash
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HandoffError {}
+
+/// Requested block isn't served by any of the data sources
+#[derive(Debug)]
+pub struct BlockNotFound(pub u64);
+
+impl std::fmt::Display for BlockNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "block {} isn't found", self.0)
+    }
+}
+
+impl std::error::Error for BlockNotFound {}
+
+/// Checks that the last emitted block is a part of the chain the data source serves
+async fn verify_handoff(
+    ds: &(dyn DataSource + Send + Sync),
+    head: &HashAndHeight,
+) -> anyhow::Result<bool> {
+    for retries in 0..=HANDOFF_RETRIES {
+        if retries > 0 {
+            tokio::time::sleep(Duration::from_secs(retries)).await;
+        }
+        let hash = ds.get_block_hash(head.height).await?;
+        if hash == head.hash {
+            return Ok(true);
+        }
+        warn!("block {}#{} has hash {} at the next data source", head.height, head.hash, hash);
+    }
+    HANDOFF_MISMATCHES_COUNTER.inc();
+    Ok(false)
+}
+
+/// Finds the latest of the recently emitted blocks which is a part of the chain the data source serves.
+/// Returns None if the last emitted block is a part of it.
+async fn find_handoff_base(
+    ds: &(dyn DataSource + Send + Sync),
+    emitted: &VecDeque<HashAndHeight>,
+    max_depth: u64,
+) -> anyhow::Result<Option<HashAndHeight>> {
+    let head = match emitted.back() {
+        Some(head) => head,
+        None => return Ok(None),
+    };
+    if verify_handoff(ds, head).await? {
+        return Ok(None);
+    }
+    for block in emitted.iter().rev().skip(1) {
+        if head.height - block.height > max_depth {
+            break;
+        }
+        if ds.get_block_hash(block.height).await? == block.hash {
+            return Ok(Some(block.clone()));
+        }
+    }
+    Err(HandoffError::NoCommonBlock {
+        head: head.clone(),
+        max_depth,
+    })?
+}
+
+/// Moves the stream back to the latest emitted block which is a part of the chain the data source serves.
+/// Returns the undo response for the replaced blocks if there are any.
+async fn rewind_to_handoff_base(
+    ds: &(dyn DataSource + Send + Sync),
+    state: &mut State,
+    emitted: &mut VecDeque<HashAndHeight>,
+    max_depth: u64,
+) -> anyhow::Result<Option<Response>> {
+    let head = match &state.0 {
+        Some(head) => head.clone(),
+        None => return Ok(None),
+    };
+    let base = match find_handoff_base(ds, emitted, max_depth).await? {
+        Some(base) => base,
+        None => return Ok(None),
+    };
+    HANDOFF_UNDOS_COUNTER.inc_by(head.height - base.height);
+    emitted.retain(|block| block.height <= base.height);
+    state.update(base.clone());
+    let undo = undo_response(head.height, &base, Cursor::new(base.clone(), base.clone()))?;
+    Ok(Some(undo))
+}
+
+/// Keeps the emitted block and its parent as possible fork bases within the reorg depth
+fn remember_block(emitted: &mut VecDeque<HashAndHeight>, block: &Block, max_depth: u64) {
+    let parent = HashAndHeight {
+        hash: block.header.parent_hash.clone(),
+        height: block.header.number.saturating_sub(1),
+    };
+    if emitted.back() != Some(&parent) {
+        emitted.push_back(parent);
+    }
+    emitted.push_back(block.into());
+    while emitted
+        .front()
+        .is_some_and(|first| block.header.number - first.height > max_depth)
+    {
+        emitted.pop_front();
+    }
+}
+
+/// Checks that the first block from the next data source continues the emitted chain.
+/// Only the parent is compared, so a block of a filtered stream which isn't a child
+/// of an emitted block or of an emitted block's parent is left to find_handoff_base.
+fn check_continuity(emitted: &VecDeque<HashAndHeight>, block: &Block) -> anyhow::Result<()> {
+    let parent_height = match block.header.number.checked_sub(1) {
+        Some(height) => height,
+        None => return Ok(()),
+    };
+    if let Some(parent) = emitted.iter().rev().find(|emitted| emitted.height == parent_height) {
+        if block.header.parent_hash != parent.hash {
+            HANDOFF_MISMATCHES_COUNTER.inc();
+            Err(HandoffError::ParentMismatch {
+                block: block.into(),
+                parent: parent.clone(),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Chain whose transaction types blocks are converted with
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChainProfile {
+    /// Ethereum and chains without own transaction types
+    #[default]
+    Ethereum,
+    /// OP stack chains like Optimism and Base
+    Optimism,
+    /// Arbitrum One and Nova
+    Arbitrum,
+    /// Polygon PoS
+    Polygon,
+}
+
+impl ChainProfile {
+    fn supports(&self, r#type: i32) -> bool {
+        use pbcodec::transaction_trace::Type;
+        if r#type == BOR_STATE_SYNC_TX_TYPE {
+            return *self == ChainProfile::Polygon;
+        }
+        match Type::try_from(r#type) {
+            Ok(Type::TrxTypeOptimismDeposit) => *self == ChainProfile::Optimism,
+            Ok(
+                Type::TrxTypeLegacy
+                | Type::TrxTypeAccessList
+                | Type::TrxTypeDynamicFee
+                | Type::TrxTypeBlob
+                | Type::TrxTypeSetCode,
+            ) => true,
+            Ok(_) => *self == ChainProfile::Arbitrum,
+            Err(_) => false,
+        }
+    }
+
+    /// Bor state sync transactions are either typed ones or, before PIP-74,
+    /// legacy ones from and to the zero address which nodes add to blocks on their own
+    pub(crate) fn is_state_sync(&self, tx: &pbcodec::TransactionTrace) -> bool {
+        use pbcodec::transaction_trace::Type;
+        let is_zero_address = |bytes: &[u8]| bytes.len() == 20 && bytes.iter().all(|byte| *byte == 0);
+        let is_legacy_state_sync =
+            tx.r#type == Type::TrxTypeLegacy as i32 && is_zero_address(&tx.from) && is_zero_address(&tx.to);
+        *self == ChainProfile::Polygon && (tx.r#type == BOR_STATE_SYNC_TX_TYPE || is_legacy_state_sync)
+    }
+
+    // system transactions are issued by the protocol and aren't signed
+    fn is_system_transaction(&self, tx: &pbcodec::TransactionTrace) -> bool {
+        use pbcodec::transaction_trace::Type;
+        match self {
+            ChainProfile::Ethereum => false,
+            ChainProfile::Optimism => tx.r#type == Type::TrxTypeOptimismDeposit as i32,
+            ChainProfile::Arbitrum => matches!(
+                Type::try_from(tx.r#type),
+                Ok(Type::TrxTypeArbitrumDeposit
+                    | Type::TrxTypeArbitrumUnsigned
+                    | Type::TrxTypeArbitrumContract
+                    | Type::TrxTypeArbitrumRetry
+                    | Type::TrxTypeArbitrumSubmitRetryable
+                    | Type::TrxTypeArbitrumInternal)
+            ),
+            ChainProfile::Polygon => self.is_state_sync(tx),
+        }
+    }
+}
+
+// rejects transaction types which an explicitly chosen chain doesn't have and drops empty signatures of system transactions
+fn apply_chain_profile(block: &mut pbcodec::Block, chain: ChainProfile) -> anyhow::Result<()> {
+    let mut warned = false;
+    for tx in block.transaction_traces.iter_mut() {
+        if !chain.supports(tx.r#type) {
+            // chains with own transaction types used to be served without a profile
+            if chain != ChainProfile::Ethereum {
+                anyhow::bail!(
+                    "tx {} has type {} which the {:?} chain profile doesn't support",
+                    prefix_hex::encode(&tx.hash),
+                    tx.r#type,
+                    chain
+                )
+            }
+            if !warned {
+                warn!(
+                    "block {} has tx {} of unknown type {}, a chain profile might be missing",
+                    block.number,
+                    prefix_hex::encode(&tx.hash),
+                    tx.r#type
+                );
+                warned = true;
+            }
+        }
+        if chain.is_system_transaction(tx) {
+            tx.v.clear();
+            tx.r.clear();
+            tx.s.clear();
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct ConversionOptions {
+    /// Reject blocks which miss header fields of canonical ethereum blocks
+    pub strict_headers: bool,
+    /// Recover public keys of transaction senders
+    pub recover_public_keys: bool,
+    /// Chain id which typed transactions are signed with
+    pub chain_id: Option<u64>,
+    /// Fetch headers of uncle blocks
+    pub uncles: bool,
+    /// Chain whose transaction types are expected
+    pub chain: ChainProfile,
+}
+
+// fills public keys of senders which match the from address
+fn fill_public_keys(block: &mut pbcodec::Block, chain_id: Option<u64>) {
+    for tx in block.transaction_traces.iter_mut() {
+        if tx.r.is_empty() && tx.s.is_empty() {
+            continue;
+        }
+        match verify::recover_public_key(tx, chain_id) {
+            Ok(public_key) if verify::public_key_address(&public_key) == tx.from => {
+                tx.public_key = public_key;
+            }
+            Ok(_) => {
+                SENDER_MISMATCHES_COUNTER.inc();
+                warn!("recovered sender of tx {} differs from its from address", prefix_hex::encode(&tx.hash));
+            }
+            Err(err) => warn!("can't recover sender of tx {}: {}", prefix_hex::encode(&tx.hash), err),
+        }
+    }
+}
+
+/// Converts a block for the graph filling in per-chain defaults of missing header fields
+/// unless the strict mode requires every field of canonical ethereum headers
+fn to_graph_block(block: Block, options: ConversionOptions) -> anyhow::Result<pbcodec::Block> {
+    if options.strict_headers {
+        verify::check_required_fields(&block.header)?;
+    }
+    let mut graph_block = pbcodec::Block::try_from(block)?;
+    apply_chain_profile(&mut graph_block, options.chain)?;
+    if options.recover_public_keys {
+        fill_public_keys(&mut graph_block, options.chain_id);
+    }
+    Ok(graph_block)
+}
+
+// only number and parent_hash are required for ForkStep::StepUndo
+fn undo_response(height: u64, base_head: &HashAndHeight, cursor: Cursor) -> anyhow::Result<Response> {
+    let graph_block = pbcodec::Block {
+        header: Some(pbcodec::BlockHeader {
+            number: height,
+            parent_hash: prefix_hex::decode(&base_head.hash)?,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    Ok(Response {
+        block: Some(prost_types::Any {
+            type_url: "type.googleapis.com/sf.ethereum.type.v2.Block".to_string(),
+            value: graph_block.encode_to_vec(),
+        }),
+        step: ForkStep::StepUndo.into(),
+        cursor: cursor.to_string(),
+    })
+}
+
 struct State(Option<HashAndHeight>);
 
 impl State {
@@ -92,16 +428,41 @@ impl From<State> for HashAndHeight {
 }
 
 pub struct Firehose {
-    portal: Arc<dyn DataSource + Sync + Send>,
+    portal: Option<Arc<dyn DataSource + Sync + Send>>,
     rpc: Option<Arc<dyn HotDataSource + Sync + Send>>,
+    // chain id to verify transactions and receipts roots of single blocks with
+    verify_roots: Option<u64>,
+    conversion: ConversionOptions,
+    // how far back the last emitted block can be replaced when switching data sources
+    max_reorg_depth: u64,
 }
 
 impl Firehose {
     pub fn new(
-        portal: Arc<dyn DataSource + Sync + Send>,
+        portal: Option<Arc<dyn DataSource + Sync + Send>>,
         rpc: Option<Arc<dyn HotDataSource + Sync + Send>>,
-    ) -> Firehose {
-        Firehose { portal, rpc }
+        verify_roots: Option<u64>,
+        conversion: ConversionOptions,
+        max_reorg_depth: u64,
+    ) -> anyhow::Result<Firehose> {
+        if portal.is_none() && rpc.is_none() {
+            anyhow::bail!("either a portal or an rpc data source is required")
+        }
+        Ok(Firehose {
+            portal,
+            rpc,
+            verify_roots,
+            conversion,
+            max_reorg_depth,
+        })
+    }
+
+    fn finalized_ds(&self) -> &(dyn DataSource + Send + Sync) {
+        match (&self.portal, &self.rpc) {
+            (_, Some(rpc)) => rpc.as_ds(),
+            (Some(portal), None) => &**portal,
+            (None, None) => unreachable!("firehose requires at least one data source"),
+        }
     }
 
     pub async fn blocks(
@@ -112,11 +473,7 @@ impl Firehose {
             anyhow::bail!("final_blocks_only requests aren't supported")
         }
 
-        let start_block = if let Some(rpc) = &self.rpc {
-            resolve_negative_start(request.start_block_num, rpc.as_ds()).await?
-        } else {
-            resolve_negative_start(request.start_block_num, &*self.portal).await?
-        };
+        let start_block = resolve_negative_start(request.start_block_num, self.finalized_ds()).await?;
         let to_block = if request.stop_block_num == 0 {
             None
         } else {
@@ -174,39 +531,53 @@ impl Firehose {
 
         let portal = self.portal.clone();
         let rpc = self.rpc.clone();
+        let conversion = self.conversion;
+        let max_reorg_depth = self.max_reorg_depth;
 
         Ok(try_stream! {
-            let portal_height = portal.get_finalized_height().await?;
-            if portal_height as i64 > state.current_block() || rpc.is_none() {
-                let req = DataRequest {
-                    from: max(state.next_block(), start_block),
-                    to: to_block,
-                    logs: logs.clone(),
-                    transactions: vec![],
-                    traces: traces.clone(),
-                };
-                let mut stream = Pin::from(portal.get_finalized_blocks(req, rpc.is_some()).await?);
-                while let Some(result) = stream.next().await {
-                    let blocks = result?;
-                    for block in blocks {
-                        state.update((&block).into());
-
-                        let graph_block = pbcodec::Block::try_from(block)?;
-
-                        yield Response {
-                            block: Some(prost_types::Any {
-                                type_url: "type.googleapis.com/sf.ethereum.type.v2.Block".to_string(),
-                                value: graph_block.encode_to_vec(),
-                            }),
-                            step: ForkStep::StepNew.into(),
-                            cursor: state.cursor().to_string(),
-                        };
+            // the last emitted blocks with their parents to roll back to if the rpc node is on another fork
+            let mut emitted: VecDeque<HashAndHeight> = state.0.iter().cloned().collect();
+
+            if let Some(portal) = portal {
+                let portal_height = portal.get_finalized_height().await?;
+                if portal_height as i64 > state.current_block() || rpc.is_none() {
+                    let req = DataRequest {
+                        from: max(state.next_block(), start_block),
+                        to: to_block,
+                        logs: logs.clone(),
+                        transactions: vec![],
+                        traces: traces.clone(),
+                        uncles: conversion.uncles,
+                    };
+                    let mut stream = Pin::from(portal.get_finalized_blocks(req, rpc.is_some()).await?);
+                    let mut first = true;
+                    while let Some(result) = stream.next().await {
+                        let blocks = result?;
+                        for block in blocks {
+                            if first {
+                                check_continuity(&emitted, &block)?;
+                                first = false;
+                            }
+                            state.update((&block).into());
+                            remember_block(&mut emitted, &block, max_reorg_depth);
+
+                            let graph_block = to_graph_block(block, conversion)?;
+
+                            yield Response {
+                                block: Some(prost_types::Any {
+                                    type_url: "type.googleapis.com/sf.ethereum.type.v2.Block".to_string(),
+                                    value: graph_block.encode_to_vec(),
+                                }),
+                                step: ForkStep::StepNew.into(),
+                                cursor: state.cursor().to_string(),
+                            };
+                        }
                     }
-                }
 
-                if let Some(to_block) = to_block {
-                    if state.current_block() as u64 == to_block {
-                        return
+                    if let Some(to_block) = to_block {
+                        if state.current_block() as u64 == to_block {
+                            return
+                        }
                     }
                 }
             }
@@ -217,6 +588,11 @@ impl Firehose {
                 return
             };
 
+            // the emitted blocks above the base are replaced with the ones the rpc node has
+            if let Some(undo) = rewind_to_handoff_base(rpc.as_ds(), &mut state, &mut emitted, max_reorg_depth).await? {
+                yield undo;
+            }
+
             let rpc_height = rpc.get_finalized_height().await?;
             if rpc_height as i64 > state.current_block() {
                 let to = if let Some(to_block) = to_block {
@@ -230,14 +606,21 @@ impl Firehose {
                     logs: logs.clone(),
                     transactions: vec![],
                     traces: traces.clone(),
+                    uncles: conversion.uncles,
                 };
                 let mut stream = Pin::from(rpc.get_finalized_blocks(req, true).await?);
+                let mut first = true;
                 while let Some(result) = stream.next().await {
                     let blocks = result?;
                     for block in blocks {
+                        if first {
+                            check_continuity(&emitted, &block)?;
+                            first = false;
+                        }
                         state.update((&block).into());
+                        remember_block(&mut emitted, &block, max_reorg_depth);
 
-                        let graph_block = pbcodec::Block::try_from(block)?;
+                        let graph_block = to_graph_block(block, conversion)?;
 
                         yield Response {
                             block: Some(prost_types::Any {
@@ -250,8 +633,16 @@ impl Firehose {
                     }
                 }
 
-                let value = HashAndHeight { height: to, hash: rpc.get_block_hash(to).await? };
-                state.update(value);
+                if state.current_block() < to as i64 {
+                    // the hot stream has to start from the end of the range even if it has no requested data,
+                    // so the emitted blocks have to be a part of the chain the end is taken from
+                    if let Some(undo) = rewind_to_handoff_base(rpc.as_ds(), &mut state, &mut emitted, max_reorg_depth).await? {
+                        yield undo;
+                    }
+                    let value = HashAndHeight { height: to, hash: rpc.get_block_hash(to).await? };
+                    emitted.push_back(value.clone());
+                    state.update(value);
+                }
 
                 if let Some(to_block) = to_block {
                     if state.current_block() as u64 == to_block {
@@ -266,6 +657,7 @@ impl Firehose {
                 logs,
                 transactions: vec![],
                 traces,
+                uncles: conversion.uncles,
             };
             let mut last_head: HashAndHeight = state.into();
             let mut stream = Pin::from(rpc.get_hot_blocks(req, last_head.clone())?);
@@ -283,28 +675,22 @@ impl Firehose {
                 };
 
                 if upd.base_head != last_head {
+                    // hot streams have every block so an update can't start above the last one
+                    if upd.base_head.height > last_head.height {
+                        Err(anyhow::anyhow!(
+                            "hot update starts at block {} while the stream is at block {}",
+                            upd.base_head.height,
+                            last_head.height
+                        ))?;
+                    }
                     // fork happened
-                    // only number and parent_hash are required for ForkStep::StepUndo
                     let cursor = Cursor::new(upd.base_head.clone(), upd.finalized_head.clone());
-                    let mut graph_block = pbcodec::Block::default();
-                    let mut header = pbcodec::BlockHeader::default();
-                    header.number = last_head.height;
-                    header.parent_hash = prefix_hex::decode(upd.base_head.hash)?;
-                    graph_block.header = Some(header);
-
-                    yield Response {
-                        block: Some(prost_types::Any {
-                            type_url: "type.googleapis.com/sf.ethereum.type.v2.Block".to_string(),
-                            value: graph_block.encode_to_vec(),
-                        }),
-                        step: ForkStep::StepUndo.into(),
-                        cursor: cursor.to_string(),
-                    };
+                    yield undo_response(last_head.height, &upd.base_head, cursor)?;
                 }
 
                 for block in upd.blocks {
                     let cursor = Cursor::new((&block).into(), upd.finalized_head.clone());
-                    let graph_block = pbcodec::Block::try_from(block)?;
+                    let graph_block = to_graph_block(block, conversion)?;
                     yield Response {
                         block: Some(prost_types::Any {
                             type_url: "type.googleapis.com/sf.ethereum.type.v2.Block".to_string(),
@@ -325,11 +711,11 @@ impl Firehose {
             anyhow::bail!("transforms aren't supported in SingleBlockRequest")
         }
 
-        let block_num = match request.reference.as_ref().unwrap() {
+        let block_num = match request.reference.as_ref().context("block reference is missing")? {
             Reference::BlockNumber(block_number) => block_number.num,
             Reference::BlockHashAndNumber(block_hash_and_number) => block_hash_and_number.num,
             Reference::Cursor(cursor) => {
-                let cursor = Cursor::try_from(&cursor.cursor).unwrap();
+                let cursor = Cursor::try_from(&cursor.cursor).map_err(|e| anyhow::anyhow!(e))?;
                 cursor.block.height
             }
         };
@@ -338,29 +724,67 @@ impl Firehose {
             from: block_num,
             to: Some(block_num),
             logs: vec![LogRequest::default()],
-            transactions: vec![TxRequest::default()],
+            transactions: vec![TxRequest {
+                state_diffs: true,
+                ..Default::default()
+            }],
             traces: vec![TraceRequest::default()],
+            uncles: self.conversion.uncles,
         };
 
-        let portal_height = self.portal.get_finalized_height().await?;
-        let mut stream = if block_num <= portal_height {
-            Pin::from(self.portal.get_finalized_blocks(req, true).await?)
-        } else {
-            if let Some(rpc) = &self.rpc {
-                let rpc_height = rpc.get_finalized_height().await?;
-                if block_num <= rpc_height {
-                    Pin::from(self.portal.get_finalized_blocks(req, true).await?)
+        let portal_height = match &self.portal {
+            Some(portal) => Some(portal.get_finalized_height().await?),
+            None => None,
+        };
+        let mut stream = match (&self.portal, portal_height) {
+            (Some(portal), Some(portal_height)) if block_num <= portal_height => {
+                Pin::from(portal.get_finalized_blocks(req, true).await?)
+            }
+            _ => {
+                if let Some(rpc) = &self.rpc {
+                    let rpc_height = rpc.get_finalized_height().await?;
+                    if block_num <= rpc_height {
+                        Pin::from(rpc.get_finalized_blocks(req, true).await?)
+                    } else {
+                        Err(BlockNotFound(block_num))?
+                    }
                 } else {
-                    anyhow::bail!("block isn't found")
+                    Err(BlockNotFound(block_num))?
                 }
-            } else {
-                anyhow::bail!("block isn't found")
             }
         };
-        let blocks = stream.next().await.unwrap()?;
-        let block = blocks.into_iter().nth(0).unwrap();
+        let blocks = stream.next().await.ok_or(BlockNotFound(block_num))??;
+        let mut block = blocks
+            .into_iter()
+            .find(|block| block.header.number == block_num)
+            .ok_or(BlockNotFound(block_num))?;
+
+        // balances of reward and withdrawal recipients can be fetched only from the rpc node
+        if let Some(rpc) = &self.rpc {
+            let mut addresses: Vec<String> = block
+                .traces
+                .iter()
+                .filter(|trace| matches!(trace.r#type, TraceType::Reward))
+                .filter_map(|trace| trace.action.as_ref().and_then(|action| action.to.clone()))
+                .chain(block.withdrawals.iter().map(|withdrawal| withdrawal.address.clone()))
+                .collect();
+            addresses.sort();
+            addresses.dedup();
+            if !addresses.is_empty() {
+                block.balances = rpc.get_balances(block_num, addresses).await?;
+            }
+        }
+
+        // receipts before byzantium can be restored only if the data source provides their state roots
+        let with_receipts = block
+            .transactions
+            .iter()
+            .all(|tx| tx.status.is_some() || tx.state_root.is_some());
+        let graph_block = to_graph_block(block, self.conversion)?;
 
-        let graph_block = pbcodec::Block::try_from(block)?;
+        if let Some(chain_id) = self.verify_roots {
+            verify_block_roots(&graph_block, chain_id, with_receipts, self.conversion.chain)?;
+        }
 
         Ok(SingleBlockResponse {
             block: Some(prost_types::Any {
@@ -377,16 +801,18 @@ impl From<LogFilter> for LogRequest {
             address: value
                 .addresses
                 .into_iter()
-                .map(|address| prefix_hex::encode(address))
+                .map(prefix_hex::encode)
                 .collect(),
             topic0: value
                 .event_signatures
                 .into_iter()
-                .map(|signature| prefix_hex::encode(signature))
+                .map(prefix_hex::encode)
                 .collect(),
             transaction: true,
             transaction_traces: true,
             transaction_logs: true,
+            // replaying every matched transaction is too expensive for streams
+            transaction_state_diffs: false,
         }
     }
 }
@@ -397,15 +823,16 @@ impl From<CallToFilter> for TraceRequest {
             address: value
                 .addresses
                 .into_iter()
-                .map(|address| prefix_hex::encode(address))
+                .map(prefix_hex::encode)
                 .collect(),
             sighash: value
                 .signatures
                 .into_iter()
-                .map(|signature| prefix_hex::encode(signature))
+                .map(prefix_hex::encode)
                 .collect(),
             transaction: true,
             transaction_logs: true,
+            transaction_state_diffs: false,
             parents: true,
         }
     }
@@ -417,7 +844,10 @@ impl TryFrom<BlockHeader> for pbcodec::BlockHeader {
     fn try_from(value: BlockHeader) -> anyhow::Result<Self, Self::Error> {
         Ok(pbcodec::BlockHeader {
             parent_hash: try_decode_hex("parent hash", &value.parent_hash)?,
-            uncle_hash: try_decode_hex("sha3 uncles", &value.sha3_uncles)?,
+            uncle_hash: try_decode_hex(
+                "sha3 uncles",
+                value.sha3_uncles.as_deref().unwrap_or(EMPTY_UNCLES_HASH),
+            )?,
             coinbase: try_decode_hex("miner", &value.miner)?,
             state_root: try_decode_hex("state root", &value.state_root)?,
             transactions_root: try_decode_hex("transactions root", &value.transactions_root)?,
@@ -426,9 +856,14 @@ impl TryFrom<BlockHeader> for pbcodec::BlockHeader {
             difficulty: Some(pbcodec::BigInt {
                 bytes: try_decode_hex("difficulty", &value.difficulty)?,
             }),
-            total_difficulty: Some(pbcodec::BigInt {
-                bytes: try_decode_hex("total difficulty", &value.total_difficulty)?,
-            }),
+            total_difficulty: value.total_difficulty.map_or::<anyhow::Result<_>, _>(
+                Ok(None),
+                |val| {
+                    Ok(Some(pbcodec::BigInt {
+                        bytes: try_decode_hex("total difficulty", &val)?,
+                    }))
+                },
+            )?,
             number: value.number,
             gas_limit: qty2int(&value.gas_limit)?,
             gas_used: qty2int(&value.gas_used)?,
@@ -437,8 +872,10 @@ impl TryFrom<BlockHeader> for pbcodec::BlockHeader {
                 nanos: 0,
             }),
             extra_data: try_decode_hex("extra data", &value.extra_data)?,
-            mix_hash: try_decode_hex("mix hash", &value.mix_hash)?,
-            nonce: qty2int(&value.nonce)?,
+            mix_hash: value
+                .mix_hash
+                .map_or(Ok(vec![0; 32]), |val| try_decode_hex("mix hash", &val))?,
+            nonce: value.nonce.map_or(Ok(0), |val| qty2int(&val))?,
             hash: try_decode_hex("hash", &value.hash)?,
             base_fee_per_gas: value.base_fee_per_gas.map_or::<anyhow::Result<_>, _>(
                 Ok(None),
@@ -448,6 +885,17 @@ impl TryFrom<BlockHeader> for pbcodec::BlockHeader {
                     }))
                 },
             )?,
+            withdrawals_root: value
+                .withdrawals_root
+                .map_or(Ok(vec![]), |val| try_decode_hex("withdrawals root", &val))?,
+            blob_gas_used: value.blob_gas_used.as_deref().map(qty2int).transpose()?,
+            excess_blob_gas: value.excess_blob_gas.as_deref().map(qty2int).transpose()?,
+            parent_beacon_root: value
+                .parent_beacon_block_root
+                .map_or(Ok(vec![]), |val| try_decode_hex("parent beacon block root", &val))?,
+            requests_hash: value
+                .requests_hash
+                .map_or(Ok(vec![]), |val| try_decode_hex("requests hash", &val))?,
         })
     }
 }
@@ -477,7 +925,21 @@ impl TryFrom<Transaction> for pbcodec::TransactionTrace {
             r: try_decode_hex("tx r", &value.r)?,
             s: try_decode_hex("tx s", &value.s)?,
             r#type: value.r#type,
-            access_list: vec![],
+            access_list: value
+                .access_list
+                .unwrap_or_default()
+                .into_iter()
+                .map(|item| {
+                    Ok(pbcodec::AccessTuple {
+                        address: try_decode_hex("access list address", &item.address)?,
+                        storage_keys: item
+                            .storage_keys
+                            .iter()
+                            .map(|key| try_decode_hex("access list storage key", key))
+                            .collect::<anyhow::Result<Vec<_>>>()?,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
             max_fee_per_gas: value.max_fee_per_gas.map_or::<anyhow::Result<_>, _>(
                 Ok(None),
                 |val| {
@@ -503,6 +965,42 @@ impl TryFrom<Transaction> for pbcodec::TransactionTrace {
             status: pbcodec::TransactionTraceStatus::Unknown.into(),
             receipt: None,
             calls: vec![],
+            blob_gas: value
+                .blob_versioned_hashes
+                .as_ref()
+                .map(|hashes| hashes.len() as u64 * GAS_PER_BLOB),
+            blob_gas_fee_cap: value
+                .max_fee_per_blob_gas
+                .map_or::<anyhow::Result<_>, _>(Ok(None), |val| {
+                    Ok(Some(pbcodec::BigInt {
+                        bytes: try_decode_hex("tx max fee per blob gas", &val)?,
+                    }))
+                })?,
+            blob_hashes: value
+                .blob_versioned_hashes
+                .unwrap_or_default()
+                .iter()
+                .map(|hash| try_decode_hex("tx blob hash", hash))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            set_code_authorizations: value
+                .authorization_list
+                .unwrap_or_default()
+                .into_iter()
+                .map(|auth| {
+                    let chain_id = auth.chain_id.to_be_bytes();
+                    let zeros = chain_id.iter().take_while(|byte| **byte == 0).count();
+                    Ok(pbcodec::SetCodeAuthorization {
+                        discarded: false,
+                        chain_id: chain_id[zeros..].to_vec(),
+                        address: try_decode_hex("authorization address", &auth.address)?,
+                        nonce: auth.nonce,
+                        v: auth.y_parity.into(),
+                        r: try_decode_hex("authorization r", &auth.r)?,
+                        s: try_decode_hex("authorization s", &auth.s)?,
+                        authority: None,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
         })
     }
 }
@@ -518,16 +1016,61 @@ impl TryFrom<Log> for pbcodec::Log {
             topics: value.topics.into_iter()
                 .map(|topic| try_decode_hex("log topic", &topic))
                 .collect::<anyhow::Result<Vec<_>>>()?,
-            index: value.transaction_index,
+            // the index within the transaction is assigned once all its logs are known
+            index: 0,
             ordinal: 0,
         })
     }
 }
 
+// geth reports reverts as "execution reverted" and parity-like nodes as "Reverted"
+fn is_reverted(trace: &Trace) -> bool {
+    trace.revert_reason.is_some()
+        || matches!(trace.error.as_deref(), Some("Reverted" | "execution reverted"))
+}
+
+fn describe_panic(code: U256) -> String {
+    let reason = match code.low_u64() {
+        _ if code > U256::from(u8::MAX) => None,
+        0x00 => Some("generic panic"),
+        0x01 => Some("assert(false)"),
+        0x11 => Some("arithmetic underflow or overflow"),
+        0x12 => Some("division or modulo by zero"),
+        0x21 => Some("enum overflow"),
+        0x22 => Some("invalid encoded storage byte array accessed"),
+        0x31 => Some("out-of-bounds array access; popping on an empty array"),
+        0x32 => Some("out-of-bounds access of an array or bytesN"),
+        0x41 => Some("out of memory"),
+        0x51 => Some("uninitialized function"),
+        _ => None,
+    };
+    match reason {
+        Some(reason) => format!("{} ({:#x})", reason, code),
+        None => format!("unknown panic code: {:#x}", code),
+    }
+}
+
+/// Decodes the reason of a reverted call from its return data the way geth reports it.
+/// Custom errors can't be decoded without the contract abi so they are left as is.
+fn decode_revert_reason(output: &[u8]) -> Option<String> {
+    // split_at_checked needs rust 1.80, which is below the 1.81 msrv
+    let (selector, args) = output.split_at_checked(4)?;
+    let reason = if selector == ERROR_SELECTOR {
+        abi::decode(&[abi::ParamType::String], args).ok()?.pop()?.into_string()?
+    } else if selector == PANIC_SELECTOR {
+        let code = abi::decode(&[abi::ParamType::Uint(256)], args).ok()?.pop()?.into_uint()?;
+        describe_panic(code)
+    } else {
+        return None;
+    };
+    Some(format!("execution reverted: {}", reason))
+}
+
 impl TryFrom<Trace> for pbcodec::Call {
     type Error = anyhow::Error;
 
     fn try_from(value: Trace) -> Result<Self, Self::Error> {
+        let reverted = is_reverted(&value);
         match value.r#type {
             TraceType::Create => {
                 let action = value.action.context("no action")?;
@@ -538,14 +1081,29 @@ impl TryFrom<Trace> for pbcodec::Call {
                 });
                 let gas = action.gas.context("no gas")?;
                 let gas_used = result.gas_used.unwrap_or("0x0".to_string());
+                let failed = value.error.is_some() || value.revert_reason.is_some();
+                let address = try_decode_hex(
+                    "trace address",
+                    &result.address.unwrap_or("0x0000000000000000000000000000000000000000".to_string())
+                )?;
+                let code = try_decode_hex("trace code", &result.output.unwrap_or("0x".to_string()))?;
+                let code_changes = if failed {
+                    vec![]
+                } else {
+                    vec![pbcodec::CodeChange {
+                        address: address.clone(),
+                        old_hash: keccak256([]).to_vec(),
+                        old_code: vec![],
+                        new_hash: keccak256(&code).to_vec(),
+                        new_code: code.clone(),
+                        ordinal: 0,
+                    }]
+                };
 
                 Ok(pbcodec::Call {
                     call_type: 5,
                     caller: try_decode_hex("trace from", &action.from.context("no from")?)?,
-                    address: try_decode_hex(
-                        "trace address",
-                        &result.address.unwrap_or("0x0000000000000000000000000000000000000000".to_string())
-                    )?,
+                    address,
                     value: action
                         .value
                         .map_or::<anyhow::Result<_>, _>(Ok(None), |val| {
@@ -553,15 +1111,16 @@ impl TryFrom<Trace> for pbcodec::Call {
                                 bytes: try_decode_hex("trace value", &val)?,
                             }))
                         })?,
-                    gas_limit: u64::from_str_radix(&gas.trim_start_matches("0x"), 16)?,
-                    gas_consumed: u64::from_str_radix(&gas_used.trim_start_matches("0x"), 16)?,
-                    return_data: prefix_hex::decode("0x")?,
-                    input: prefix_hex::decode("0x")?,
-                    status_failed: value.error.is_some() || value.revert_reason.is_some(),
-                    status_reverted: value.revert_reason.is_some(),
+                    gas_limit: u64::from_str_radix(gas.trim_start_matches("0x"), 16)?,
+                    gas_consumed: u64::from_str_radix(gas_used.trim_start_matches("0x"), 16)?,
+                    return_data: code,
+                    input: try_decode_hex("trace init", &action.input.unwrap_or("0x".to_string()))?,
+                    status_failed: failed,
+                    status_reverted: reverted,
                     failure_reason: value
                         .error
                         .unwrap_or_else(|| value.revert_reason.unwrap_or_default()),
+                    code_changes,
                     ..Default::default()
                 })
             }
@@ -596,32 +1155,384 @@ impl TryFrom<Trace> for pbcodec::Call {
                                 bytes: try_decode_hex("trace value", &val)?,
                             }))
                         })?,
-                    gas_limit: u64::from_str_radix(&gas.trim_start_matches("0x"), 16)?,
-                    gas_consumed: u64::from_str_radix(&gas_used.trim_start_matches("0x"), 16)?,
+                    gas_limit: u64::from_str_radix(gas.trim_start_matches("0x"), 16)?,
+                    gas_consumed: u64::from_str_radix(gas_used.trim_start_matches("0x"), 16)?,
                     return_data: try_decode_hex("trace output", &output)?,
                     input: try_decode_hex("trace input", &action.input.context("no input")?)?,
                     status_failed: value.error.is_some() || value.revert_reason.is_some(),
-                    status_reverted: value.revert_reason.is_some(),
+                    status_reverted: reverted,
                     failure_reason: value
                         .error
                         .unwrap_or_else(|| value.revert_reason.unwrap_or_default()),
                     ..Default::default()
                 })
             }
-            TraceType::Suicide | TraceType::Reward => anyhow::bail!("unsupported trace type"),
+            TraceType::Suicide => {
+                let action = value.action.context("no action")?;
+                Ok(pbcodec::Call {
+                    call_type: pbcodec::CallType::Call.into(),
+                    caller: try_decode_hex("trace from", &action.from.context("no from")?)?,
+                    address: try_decode_hex("trace refund address", &action.to.context("no to")?)?,
+                    value: action
+                        .value
+                        .map_or::<anyhow::Result<_>, _>(Ok(None), |val| {
+                            Ok(Some(pbcodec::BigInt {
+                                bytes: try_decode_hex("trace balance", &val)?,
+                            }))
+                        })?,
+                    suicide: true,
+                    status_failed: value.error.is_some(),
+                    failure_reason: value.error.unwrap_or_default(),
+                    ..Default::default()
+                })
+            }
+            TraceType::Reward => anyhow::bail!("reward traces aren't calls"),
         }
     }
 }
 
-fn get_tx_trace_status(calls: &Vec<pbcodec::Call>) -> i32 {
-    let call = &calls[0];
-    if call.status_failed && call.state_reverted {
-        pbcodec::TransactionTraceStatus::Reverted.into()
-    } else if call.status_failed {
-        pbcodec::TransactionTraceStatus::Failed.into()
+/// Converts traces of a transaction to calls ordered depth-first.
+/// Calls are indexed from 1 and the parent index of the root call is 0.
+/// State changes of failed calls and all of their descendants are reverted.
+fn build_calls(mut traces: Vec<Trace>) -> anyhow::Result<Vec<pbcodec::Call>> {
+    traces.retain(|trace| matches!(trace.r#type, TraceType::Call | TraceType::Create | TraceType::Suicide));
+    traces.sort_by(|a, b| a.trace_address.cmp(&b.trace_address));
+
+    let mut indexes: HashMap<Vec<u32>, u32> = HashMap::new();
+    let mut calls: Vec<pbcodec::Call> = Vec::with_capacity(traces.len());
+    for (position, trace) in traces.into_iter().enumerate() {
+        let index = u32::try_from(position + 1)?;
+        // filtered traces might come without some of the ancestors
+        let parent_index = (0..trace.trace_address.len())
+            .rev()
+            .find_map(|len| indexes.get(&trace.trace_address[..len]))
+            .copied()
+            .unwrap_or(0);
+        let depth = u32::try_from(trace.trace_address.len())?;
+        indexes.insert(trace.trace_address.clone(), index);
+
+        let mut call = pbcodec::Call::try_from(trace)?;
+        call.index = index;
+        call.parent_index = parent_index;
+        call.depth = depth;
+        if call.status_reverted {
+            if let Some(reason) = decode_revert_reason(&call.return_data) {
+                call.failure_reason = reason;
+            }
+        }
+        let parent = (parent_index as usize).checked_sub(1).map(|pos| &calls[pos]);
+        call.state_reverted = call.status_failed || parent.is_some_and(|parent| parent.state_reverted);
+        calls.push(call);
+    }
+    Ok(calls)
+}
+
+fn is_delegate(call: &pbcodec::Call) -> bool {
+    call.call_type == pbcodec::CallType::Delegate as i32 || call.call_type == pbcodec::CallType::Callcode as i32
+}
+
+// address of the contract whose storage and logs the call works with
+fn get_context_address(call: &pbcodec::Call) -> &[u8] {
+    if is_delegate(call) {
+        &call.caller
     } else {
+        &call.address
+    }
+}
+
+/// Assigns logs to the calls which emitted them.
+/// Logs go in execution order so a log is attached to the first call at or after the call
+/// of the previous log which runs in the context of the log address, or to the closest ancestor.
+/// Calls which failed or have a failed ancestor can't have logs.
+///
+/// Traces don't tell how many logs a call emitted, so this is a guess. A log emitted by a caller
+/// after its nested call returned goes to a later call in the context of the same address if there is one,
+/// e.g. when the caller then calls itself.
+fn attach_logs(calls: &mut [pbcodec::Call], logs: &[pbcodec::Log]) {
+    let failed: Vec<bool> = calls.iter().map(|call| call.state_reverted).collect();
+
+    let mut current = 0;
+    for log in logs {
+        let is_emitter = |pos: &usize| {
+            !failed[*pos] && !calls[*pos].suicide && get_context_address(&calls[*pos]) == log.address
+        };
+        let ancestors = std::iter::successors(calls.get(current), |call| {
+            (call.parent_index as usize).checked_sub(1).map(|pos| &calls[pos])
+        })
+        .map(|call| call.index as usize - 1);
+        let emitter = (current..calls.len())
+            .find(is_emitter)
+            .or_else(|| ancestors.skip(1).find(is_emitter))
+            .or_else(|| (0..current).rev().find(is_emitter));
+
+        if let Some(mut pos) = emitter {
+            // proxies emit logs from the code they delegate to
+            while let Some(next) = calls.get(pos + 1) {
+                if next.parent_index != calls[pos].index || !is_delegate(next) || failed[pos + 1] {
+                    break;
+                }
+                pos += 1;
+            }
+            calls[pos].logs.push(log.clone());
+            current = pos;
+        }
+    }
+}
+
+/// Adds state changes of a transaction to its root call since diffs aren't traced per call.
+/// Code of created contracts is already known from the creating calls.
+fn add_state_changes(calls: &mut [pbcodec::Call], state_diffs: Vec<StateDiff>) -> anyhow::Result<()> {
+    let created: HashSet<Vec<u8>> = calls
+        .iter()
+        .flat_map(|call| call.code_changes.iter())
+        .map(|change| change.address.clone())
+        .collect();
+    let call = match calls.first_mut() {
+        Some(call) => call,
+        // diffs can't be attributed without the call tree
+        None => return Ok(()),
+    };
+
+    let to_bigint = |value: Option<String>| {
+        value.map_or::<anyhow::Result<_>, _>(Ok(None), |val| {
+            Ok(Some(pbcodec::BigInt {
+                bytes: try_decode_hex("balance", &val)?,
+            }))
+        })
+    };
+    let to_nonce = |value: Option<String>| value.as_deref().map_or(Ok(0), qty2int);
+    let to_code = |value: Option<String>| value.map_or(Ok(vec![]), |val| try_decode_hex("code", &val));
+    // slots which are created or deleted hold zero
+    let to_slot = |value: Option<String>| {
+        value.map_or(Ok(vec![0; 32]), |val| try_decode_hex("storage value", &val))
+    };
+
+    for diff in state_diffs {
+        if matches!(diff.kind, StateDiffKind::NoChange) {
+            continue;
+        }
+        let address = try_decode_hex("state diff address", &diff.address)?;
+        match diff.key.as_str() {
+            "balance" => call.balance_changes.push(pbcodec::BalanceChange {
+                address,
+                old_value: to_bigint(diff.prev)?,
+                new_value: to_bigint(diff.next)?,
+                reason: pbcodec::balance_change::Reason::Unknown.into(),
+                ordinal: 0,
+            }),
+            "nonce" => call.nonce_changes.push(pbcodec::NonceChange {
+                address,
+                old_value: to_nonce(diff.prev)?,
+                new_value: to_nonce(diff.next)?,
+                ordinal: 0,
+            }),
+            "code" if created.contains(&address) => {}
+            "code" => {
+                let old_code = to_code(diff.prev)?;
+                let new_code = to_code(diff.next)?;
+                call.code_changes.push(pbcodec::CodeChange {
+                    address,
+                    old_hash: keccak256(&old_code).to_vec(),
+                    old_code,
+                    new_hash: keccak256(&new_code).to_vec(),
+                    new_code,
+                    ordinal: 0,
+                });
+            }
+            key => call.storage_changes.push(pbcodec::StorageChange {
+                address,
+                key: try_decode_hex("storage key", key)?,
+                old_value: to_slot(diff.prev)?,
+                new_value: to_slot(diff.next)?,
+                ordinal: 0,
+            }),
+        }
+    }
+    Ok(())
+}
+
+/// Assigns block-wide execution ordinals: the transaction begins, then each call begins,
+/// emits its logs interleaved with its nested calls by log index before it ends, and then the transaction ends.
+/// Logs which can't be placed relative to nested calls without logs are emitted before them.
+fn assign_ordinals(tx: &mut pbcodec::TransactionTrace, ordinal: &mut u64) {
+    let mut next = || {
+        let value = *ordinal;
+        *ordinal += 1;
+        value
+    };
+
+    tx.begin_ordinal = next();
+
+    // logs emitted before a call begins precede all logs of the call and the calls after it
+    let mut bounds = vec![u32::MAX; tx.calls.len()];
+    let mut first = u32::MAX;
+    for (pos, call) in tx.calls.iter().enumerate().rev() {
+        first = call.logs.iter().map(|log| log.block_index).fold(first, u32::min);
+        bounds[pos] = first;
+    }
+
+    let mut emitted = vec![0; tx.calls.len()];
+    let mut open: Vec<usize> = vec![];
+    for (pos, bound) in bounds.into_iter().enumerate() {
+        let parent_index = tx.calls[pos].parent_index;
+        while let Some(&top) = open.last() {
+            if tx.calls[top].index == parent_index {
+                break;
+            }
+            emit_logs(&mut tx.calls, &[top], &mut emitted, u32::MAX, &mut next);
+            tx.calls[top].end_ordinal = next();
+            open.pop();
+        }
+        emit_logs(&mut tx.calls, &open, &mut emitted, bound, &mut next);
+        let call = &mut tx.calls[pos];
+        call.begin_ordinal = next();
+        for change in call.storage_changes.iter_mut() {
+            change.ordinal = next();
+        }
+        for change in call.balance_changes.iter_mut() {
+            change.ordinal = next();
+        }
+        for change in call.nonce_changes.iter_mut() {
+            change.ordinal = next();
+        }
+        for change in call.code_changes.iter_mut() {
+            change.ordinal = next();
+        }
+        open.push(pos);
+    }
+    while let Some(top) = open.pop() {
+        emit_logs(&mut tx.calls, &[top], &mut emitted, u32::MAX, &mut next);
+        tx.calls[top].end_ordinal = next();
+    }
+
+    let log_ordinals: HashMap<u32, u64> = tx
+        .calls
+        .iter()
+        .flat_map(|call| call.logs.iter())
+        .map(|log| (log.block_index, log.ordinal))
+        .collect();
+    if let Some(receipt) = tx.receipt.as_mut() {
+        for log in receipt.logs.iter_mut() {
+            log.ordinal = match log_ordinals.get(&log.block_index) {
+                Some(ordinal) => *ordinal,
+                // logs of unknown calls
+                None => next(),
+            };
+        }
+    }
+
+    tx.end_ordinal = next();
+}
+
+/// Assigns ordinals to not yet emitted logs of the given calls which precede the given log index.
+fn emit_logs(
+    calls: &mut [pbcodec::Call],
+    open: &[usize],
+    emitted: &mut [usize],
+    before: u32,
+    next: &mut dyn FnMut() -> u64,
+) {
+    let mut pending: Vec<(u32, usize)> = open
+        .iter()
+        .flat_map(|&pos| {
+            calls[pos].logs[emitted[pos]..]
+                .iter()
+                .take_while(|log| log.block_index < before)
+                .map(move |log| (log.block_index, pos))
+        })
+        .collect();
+    pending.sort();
+    for (_, pos) in pending {
+        calls[pos].logs[emitted[pos]].ordinal = next();
+        emitted[pos] += 1;
+    }
+}
+
+/// The receipt status tells whether a transaction failed and the root call tells reverts
+/// from other failures. Before byzantium only the root call has the status.
+fn get_tx_trace_status(receipt_status: Option<i32>, calls: &[pbcodec::Call]) -> i32 {
+    let root = calls.first();
+    let failed = match (receipt_status, root) {
+        (Some(status), _) => status == 0,
+        (None, Some(call)) => call.status_failed,
+        (None, None) => return pbcodec::TransactionTraceStatus::Unknown.into(),
+    };
+    if !failed {
         pbcodec::TransactionTraceStatus::Succeeded.into()
+    } else if root.is_some_and(|call| call.status_reverted) {
+        pbcodec::TransactionTraceStatus::Reverted.into()
+    } else {
+        pbcodec::TransactionTraceStatus::Failed.into()
+    }
+}
+
+fn to_wei(label: &'static str, value: &str) -> anyhow::Result<U256> {
+    U256::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| format_err!("invalid {}: {}", label, value))
+}
+
+fn to_bigint(value: U256) -> pbcodec::BigInt {
+    let mut bytes = [0; 32];
+    value.to_big_endian(&mut bytes);
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    pbcodec::BigInt {
+        bytes: bytes[zeros..].to_vec(),
+    }
+}
+
+/// Converts rewards and withdrawals, which are paid out once all transactions are executed, to balance changes.
+/// Balances before each payment are restored from the balances at the end of the block,
+/// so nothing is converted if the data source didn't provide them.
+fn get_block_balance_changes(
+    rewards: Vec<Trace>,
+    withdrawals: Vec<Withdrawal>,
+    balances: &HashMap<String, String>,
+) -> anyhow::Result<Vec<pbcodec::BalanceChange>> {
+    if balances.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut payments = vec![];
+    for reward in rewards {
+        let action = reward.action.context("no reward action")?;
+        let reason = match action.reward_type {
+            Some(RewardType::Block) => pbcodec::balance_change::Reason::RewardMineBlock,
+            Some(RewardType::Uncle) => pbcodec::balance_change::Reason::RewardMineUncle,
+            _ => pbcodec::balance_change::Reason::Unknown,
+        };
+        let value = to_wei("reward value", &action.value.context("no reward value")?)?;
+        payments.push((action.to.context("no reward author")?, value, reason));
+    }
+    for withdrawal in withdrawals {
+        // withdrawals are denominated in gwei
+        let amount = to_wei("withdrawal amount", &withdrawal.amount)? * U256::exp10(9);
+        payments.push((withdrawal.address, amount, pbcodec::balance_change::Reason::Withdrawal));
     }
+
+    // payments are taken back from the last one
+    let mut current: HashMap<String, U256> = HashMap::new();
+    let mut changes = vec![];
+    for (address, value, reason) in payments.into_iter().rev() {
+        let new_value = match current.get(&address) {
+            Some(balance) => *balance,
+            None => {
+                let balance = balances.get(&address).with_context(|| format!("no balance of {}", address))?;
+                to_wei("balance", balance)?
+            }
+        };
+        let old_value = new_value
+            .checked_sub(value)
+            .with_context(|| format!("balance of {} is lower than its payment", address))?;
+        current.insert(address.clone(), old_value);
+        changes.push(pbcodec::BalanceChange {
+            address: try_decode_hex("balance change address", &address)?,
+            old_value: Some(to_bigint(old_value)),
+            new_value: Some(to_bigint(new_value)),
+            reason: reason.into(),
+            ordinal: 0,
+        });
+    }
+    changes.reverse();
+    Ok(changes)
 }
 
 impl TryFrom<Block> for pbcodec::Block {
@@ -630,30 +1541,28 @@ impl TryFrom<Block> for pbcodec::Block {
     fn try_from(value: Block) -> Result<Self, Self::Error> {
         let mut logs_by_tx: HashMap<u32, Vec<Log>> = HashMap::new();
         for log in value.logs {
-            if logs_by_tx.contains_key(&log.transaction_index) {
-                logs_by_tx
-                    .get_mut(&log.transaction_index)
-                    .unwrap()
-                    .push(log);
-            } else {
-                logs_by_tx.insert(log.transaction_index, vec![log]);
-            }
+            logs_by_tx.entry(log.transaction_index).or_default().push(log);
         }
 
         let mut traces_by_tx: HashMap<u32, Vec<Trace>> = HashMap::new();
+        let mut rewards = vec![];
         for trace in value.traces {
-            if traces_by_tx.contains_key(&trace.transaction_index) {
-                traces_by_tx
-                    .get_mut(&trace.transaction_index)
-                    .unwrap()
-                    .push(trace);
+            // rewards don't belong to any transaction
+            if matches!(trace.r#type, TraceType::Reward) {
+                rewards.push(trace);
             } else {
-                traces_by_tx.insert(trace.transaction_index, vec![trace]);
+                traces_by_tx.entry(trace.transaction_index).or_default().push(trace);
             }
         }
 
+        let mut state_diffs_by_tx: HashMap<u32, Vec<StateDiff>> = HashMap::new();
+        for diff in value.state_diffs {
+            state_diffs_by_tx.entry(diff.transaction_index).or_default().push(diff);
+        }
+
+        let mut ordinal = 0;
         let transaction_traces = value.transactions.into_iter().map(|tx| {
-            let logs = logs_by_tx.remove(&tx.transaction_index)
+            let mut logs = logs_by_tx.remove(&tx.transaction_index)
                 .unwrap_or_default()
                 .into_iter()
                 .map(|log| {
@@ -662,40 +1571,533 @@ impl TryFrom<Block> for pbcodec::Block {
                         .with_context(|| format!("log_index: {}", log_index))
                 })
                 .collect::<anyhow::Result<Vec<_>>>()?;
-            let calls = traces_by_tx.remove(&tx.transaction_index)
-                .unwrap_or_default()
-                .into_iter()
-                .filter_map(|trace| {
-                    match trace.r#type {
-                        TraceType::Call | TraceType::Create => Some(pbcodec::Call::try_from(trace)),
-                        TraceType::Reward | TraceType::Suicide => None,
-                    }
-                })
-                .collect::<anyhow::Result<Vec<pbcodec::Call>>>()?;
+            logs.sort_by_key(|log| log.block_index);
+            for (index, log) in logs.iter_mut().enumerate() {
+                log.index = u32::try_from(index)?;
+            }
+            let mut calls = build_calls(traces_by_tx.remove(&tx.transaction_index).unwrap_or_default())?;
+            attach_logs(&mut calls, &logs);
+            if let Some(state_diffs) = state_diffs_by_tx.remove(&tx.transaction_index) {
+                add_state_changes(&mut calls, state_diffs)?;
+            }
+            // the bloom can be computed only if all logs of the transaction were requested
+            let logs_bloom = match &tx.logs_bloom {
+                Some(logs_bloom) => try_decode_hex("logs bloom", logs_bloom)?,
+                None => verify::logs_bloom(&logs).as_bytes().to_vec(),
+            };
             let receipt = pbcodec::TransactionReceipt {
-                state_root: vec![],
+                state_root: tx.state_root.as_deref().map_or(Ok(vec![]), |root| try_decode_hex("state root", root))?,
                 cumulative_gas_used: qty2int(&tx.cumulative_gas_used)?,
-                logs_bloom: prefix_hex::decode("0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000")?,
+                logs_bloom,
                 logs,
+                l1_fee: tx.l1_fee.as_ref().map_or::<anyhow::Result<_>, _>(Ok(None), |val| {
+                    Ok(Some(pbcodec::BigInt {
+                        bytes: try_decode_hex("l1 fee", val)?,
+                    }))
+                })?,
+                l1_gas_price: tx.l1_gas_price.as_ref().map_or::<anyhow::Result<_>, _>(Ok(None), |val| {
+                    Ok(Some(pbcodec::BigInt {
+                        bytes: try_decode_hex("l1 gas price", val)?,
+                    }))
+                })?,
+                l1_gas_used: tx.l1_gas_used.as_deref().map(qty2int).transpose()?,
+                l1_fee_scalar: tx.l1_fee_scalar.clone(),
             };
+            let receipt_status = tx.status;
             let mut tx_trace = pbcodec::TransactionTrace::try_from(tx)?;
-            tx_trace.status = get_tx_trace_status(&calls);
+            tx_trace.status = get_tx_trace_status(receipt_status, &calls);
+            if let Some(call) = calls.first() {
+                tx_trace.return_data = call.return_data.clone();
+            }
             tx_trace.receipt = Some(receipt);
             tx_trace.calls = calls;
+            assign_ordinals(&mut tx_trace, &mut ordinal);
             Ok(tx_trace)
         })
         .collect::<anyhow::Result<Vec<_>>>()?;
 
+        let mut balance_changes = get_block_balance_changes(rewards, value.withdrawals, &value.balances)?;
+        for change in balance_changes.iter_mut() {
+            change.ordinal = ordinal;
+            ordinal += 1;
+        }
+
         Ok(pbcodec::Block {
             ver: 2,
             hash: try_decode_hex("hash", &value.header.hash.clone())?,
             number: value.header.number,
-            size: value.header.size,
+            size: value.header.size.unwrap_or_default(),
             header: Some(pbcodec::BlockHeader::try_from(value.header)?),
-            uncles: vec![],
+            uncles: value
+                .uncles
+                .into_iter()
+                .map(pbcodec::BlockHeader::try_from)
+                .collect::<anyhow::Result<Vec<_>>>()?,
             transaction_traces,
-            balance_changes: vec![],
+            balance_changes,
             code_changes: vec![],
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_state_changes, apply_chain_profile, assign_ordinals, attach_logs, build_calls, check_continuity,
+        decode_revert_reason, find_handoff_base, get_block_balance_changes, get_tx_trace_status,
+        rewind_to_handoff_base, remember_block, BlockNotFound, ChainProfile, ConversionOptions, Firehose,
+        HandoffError, State, ERROR_SELECTOR, PANIC_SELECTOR,
+    };
+    use ethers_core::abi;
+    use ethers_core::types::U256;
+    use crate::datasource::{
+        test_block, BlockStream, CallType, DataRequest, DataSource, HashAndHeight, RewardType, StateDiff,
+        StateDiffKind, Trace, TraceAction, TraceResult, TraceType, Withdrawal,
+    };
+    use crate::pbcodec;
+    use crate::pbfirehose::single_block_request::{BlockNumber, Reference};
+    use crate::pbfirehose::{ForkStep, SingleBlockRequest};
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::Arc;
+
+    // data source which only knows hashes of its chain
+    struct Chain(HashMap<u64, String>);
+
+    #[async_trait::async_trait]
+    impl DataSource for Chain {
+        async fn get_finalized_blocks(&self, _: DataRequest, _: bool) -> anyhow::Result<BlockStream> {
+            Ok(Box::new(futures_util::stream::empty()))
+        }
+
+        async fn get_finalized_height(&self) -> anyhow::Result<u64> {
+            Ok(*self.0.keys().max().unwrap())
+        }
+
+        async fn get_block_hash(&self, height: u64) -> anyhow::Result<String> {
+            Ok(self.0[&height].clone())
+        }
+
+        async fn get_balances(&self, _: u64, _: Vec<String>) -> anyhow::Result<HashMap<String, String>> {
+            Ok(HashMap::new())
+        }
+    }
+
+    fn address(n: u8) -> String {
+        format!("0x{:040x}", n)
+    }
+
+    fn trace(trace_address: Vec<u32>) -> Trace {
+        call(trace_address, CallType::Call, 1, 2)
+    }
+
+    fn call(trace_address: Vec<u32>, r#type: CallType, from: u8, to: u8) -> Trace {
+        Trace {
+            transaction_index: 0,
+            trace_address,
+            r#type: TraceType::Call,
+            error: None,
+            revert_reason: None,
+            action: Some(TraceAction {
+                from: Some(address(from)),
+                to: Some(address(to)),
+                value: Some("0x0".to_string()),
+                gas: Some("0x5208".to_string()),
+                input: Some("0x".to_string()),
+                r#type: Some(r#type),
+                reward_type: None,
+            }),
+            result: Some(TraceResult {
+                gas_used: Some("0x0".to_string()),
+                address: None,
+                output: Some("0x".to_string()),
+            }),
+        }
+    }
+
+    #[test]
+    fn build_call_tree() {
+        let traces = vec![
+            trace(vec![1]),
+            trace(vec![]),
+            trace(vec![0, 0]),
+            trace(vec![0]),
+            trace(vec![1, 0, 0]),
+        ];
+        let calls = build_calls(traces).unwrap();
+        let tree: Vec<_> = calls
+            .iter()
+            .map(|call| (call.index, call.parent_index, call.depth))
+            .collect();
+        // the call [1, 0] is missing, e.g. filtered out
+        assert_eq!(tree, vec![(1, 0, 0), (2, 1, 1), (3, 2, 2), (4, 1, 1), (5, 4, 3)]);
+    }
+
+    #[test]
+    fn attach_logs_to_emitting_calls() {
+        let mut failed = call(vec![1], CallType::Call, 2, 3);
+        failed.error = Some("Reverted".to_string());
+        let traces = vec![
+            call(vec![], CallType::Call, 1, 2),
+            call(vec![0], CallType::Delegatecall, 2, 4),
+            failed,
+            call(vec![2], CallType::Call, 2, 3),
+            call(vec![3], CallType::Call, 2, 5),
+        ];
+        let mut calls = build_calls(traces).unwrap();
+
+        let logs: Vec<_> = [2, 3, 2, 5, 2]
+            .into_iter()
+            .enumerate()
+            .map(|(index, n)| pbcodec::Log {
+                address: prefix_hex::decode::<Vec<u8>>(address(n)).unwrap(),
+                index: index as u32,
+                ..Default::default()
+            })
+            .collect();
+        attach_logs(&mut calls, &logs);
+
+        let logs: Vec<Vec<u32>> = calls
+            .iter()
+            .map(|call| call.logs.iter().map(|log| log.index).collect())
+            .collect();
+        // the delegate call emits in the context of its caller and the reverted call emits nothing
+        assert_eq!(logs, vec![vec![], vec![0, 2, 4], vec![], vec![1], vec![3]]);
+    }
+
+    #[test]
+    fn assign_execution_ordinals() {
+        let traces = vec![
+            trace(vec![]),
+            trace(vec![0]),
+            trace(vec![0, 0]),
+            trace(vec![1]),
+        ];
+        let mut calls = build_calls(traces).unwrap();
+        let log = pbcodec::Log {
+            block_index: 7,
+            ..Default::default()
+        };
+        calls[1].logs.push(log.clone());
+
+        let mut tx = pbcodec::TransactionTrace {
+            calls,
+            receipt: Some(pbcodec::TransactionReceipt {
+                logs: vec![log],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut ordinal = 10;
+        assign_ordinals(&mut tx, &mut ordinal);
+
+        assert_eq!((tx.begin_ordinal, tx.end_ordinal), (10, 20));
+        let calls: Vec<_> = tx
+            .calls
+            .iter()
+            .map(|call| (call.begin_ordinal, call.end_ordinal))
+            .collect();
+        assert_eq!(calls, vec![(11, 19), (12, 16), (14, 15), (17, 18)]);
+        assert_eq!(tx.calls[1].logs[0].ordinal, 13);
+        assert_eq!(tx.receipt.unwrap().logs[0].ordinal, 13);
+        assert_eq!(ordinal, 21);
+    }
+
+    #[test]
+    fn interleave_logs_with_nested_calls() {
+        let mut calls = build_calls(vec![trace(vec![]), trace(vec![0])]).unwrap();
+        let log = |block_index: u32| pbcodec::Log {
+            block_index,
+            ..Default::default()
+        };
+        calls[0].logs = vec![log(0), log(2)];
+        calls[1].logs = vec![log(1)];
+
+        let mut tx = pbcodec::TransactionTrace {
+            calls,
+            ..Default::default()
+        };
+        let mut ordinal = 0;
+        assign_ordinals(&mut tx, &mut ordinal);
+
+        // the parent log, the nested call with its log and the second parent log
+        let root = &tx.calls[0];
+        let nested = &tx.calls[1];
+        assert_eq!(root.begin_ordinal, 1);
+        assert_eq!(root.logs[0].ordinal, 2);
+        assert_eq!((nested.begin_ordinal, nested.logs[0].ordinal, nested.end_ordinal), (3, 4, 5));
+        assert_eq!(root.logs[1].ordinal, 6);
+        assert_eq!(root.end_ordinal, 7);
+        assert_eq!(tx.end_ordinal, 8);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn walk_back_to_common_block() {
+        let block = |height: u64, fork: &str| HashAndHeight {
+            hash: format!("0x{}{}", fork, height),
+            height,
+        };
+        let emitted: VecDeque<_> = (4..=8).map(|height| block(height, "a")).collect();
+        let chain = Chain(
+            (4..=8)
+                .map(|height| (height, block(height, if height < 7 { "a" } else { "b" }).hash))
+                .collect(),
+        );
+
+        let base = find_handoff_base(&chain, &emitted, 10).await.unwrap();
+        assert_eq!(base, Some(block(6, "a")));
+
+        let err = find_handoff_base(&chain, &emitted, 1).await.expect_err("handoff should fail");
+        assert!(matches!(
+            err.downcast_ref::<HandoffError>(),
+            Some(HandoffError::NoCommonBlock { head, max_depth: 1 }) if *head == block(8, "a")
+        ));
+
+        let chain = Chain((4..=8).map(|height| (height, block(height, "a").hash)).collect());
+        assert_eq!(find_handoff_base(&chain, &emitted, 10).await.unwrap(), None);
+    }
+
+    #[test]
+    fn check_parents_of_next_blocks() {
+        let block = |number: u64, parent_hash: &str| {
+            test_block(number, format!("0x{}", number), parent_hash.to_string())
+        };
+        let mut emitted = VecDeque::new();
+        remember_block(&mut emitted, &block(5, "0x4"), 10);
+
+        check_continuity(&emitted, &block(6, "0x5")).unwrap();
+        // the parent of an emitted block is known as well
+        check_continuity(&emitted, &block(5, "0x4")).unwrap();
+        // nothing is known about the parent of a block after a gap
+        check_continuity(&emitted, &block(8, "0xff")).unwrap();
+
+        let err = check_continuity(&emitted, &block(6, "0xff")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<HandoffError>(),
+            Some(HandoffError::ParentMismatch { parent, .. }) if parent.hash == "0x5"
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rewind_stream_to_handoff_base() {
+        let block = |height: u64, fork: &str| HashAndHeight {
+            hash: format!("0x{}{}", fork, height),
+            height,
+        };
+        let mut emitted: VecDeque<_> = (4..=8).map(|height| block(height, "a")).collect();
+        let mut state = State(Some(block(8, "a")));
+        let chain = Chain(
+            (4..=8)
+                .map(|height| (height, block(height, if height < 7 { "a" } else { "b" }).hash))
+                .collect(),
+        );
+
+        let undo = rewind_to_handoff_base(&chain, &mut state, &mut emitted, 10).await.unwrap();
+        assert_eq!(undo.unwrap().step, i32::from(ForkStep::StepUndo));
+        assert_eq!(state.0, Some(block(6, "a")));
+        assert_eq!(emitted.back(), Some(&block(6, "a")));
+
+        // the stream is a part of the chain now
+        let undo = rewind_to_handoff_base(&chain, &mut state, &mut emitted, 10).await.unwrap();
+        assert!(undo.is_none());
+    }
+
+    #[test]
+    fn convert_suicide_traces() {
+        let mut suicide = call(vec![0], CallType::Call, 2, 3);
+        suicide.r#type = TraceType::Suicide;
+        suicide.result = None;
+        let calls = build_calls(vec![trace(vec![]), suicide]).unwrap();
+        assert!(calls[1].suicide);
+        assert_eq!(calls[1].parent_index, 1);
+        assert_eq!(calls[1].address, prefix_hex::decode::<Vec<u8>>(address(3)).unwrap());
+    }
+
+    #[test]
+    fn convert_rewards_to_balance_changes() {
+        let reward = |author: u8, value: &str, reward_type: RewardType| {
+            let mut reward = call(vec![], CallType::Call, 0, author);
+            reward.r#type = TraceType::Reward;
+            if let Some(action) = reward.action.as_mut() {
+                action.value = Some(value.to_string());
+                action.reward_type = Some(reward_type);
+            }
+            reward
+        };
+        let rewards = vec![
+            reward(4, "0x1bc16d674ec80000", RewardType::Block),
+            reward(5, "0x18493fba64ef0000", RewardType::Uncle),
+        ];
+        // 5 ether and the uncle reward
+        let balances = HashMap::from([
+            (address(4), "0x4563918244f40000".to_string()),
+            (address(5), "0x18493fba64ef0000".to_string()),
+        ]);
+
+        let changes = get_block_balance_changes(rewards, vec![], &balances).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].reason, pbcodec::balance_change::Reason::RewardMineBlock as i32);
+        assert_eq!(changes[0].address, prefix_hex::decode::<Vec<u8>>(address(4)).unwrap());
+        // 3 ether before and 5 ether after the 2 ether reward
+        assert_eq!(changes[0].old_value.as_ref().unwrap().bytes, vec![0x29, 0xa2, 0x24, 0x1a, 0xf6, 0x2c, 0, 0]);
+        assert_eq!(changes[0].new_value.as_ref().unwrap().bytes, vec![0x45, 0x63, 0x91, 0x82, 0x44, 0xf4, 0, 0]);
+        assert_eq!(changes[1].reason, pbcodec::balance_change::Reason::RewardMineUncle as i32);
+        assert_eq!(changes[1].address, prefix_hex::decode::<Vec<u8>>(address(5)).unwrap());
+        assert!(changes[1].old_value.as_ref().unwrap().bytes.is_empty());
+        assert_eq!(changes[1].new_value.as_ref().unwrap().bytes, vec![0x18, 0x49, 0x3f, 0xba, 0x64, 0xef, 0, 0]);
+    }
+
+    #[test]
+    fn convert_withdrawals_to_balance_changes() {
+        let withdrawal = |index: u64, amount: &str| Withdrawal {
+            index,
+            validator_index: 0,
+            address: address(1),
+            amount: amount.to_string(),
+        };
+        // 1 and 2 ether
+        let withdrawals = vec![withdrawal(0, "0x3b9aca00"), withdrawal(1, "0x77359400")];
+        let balances = HashMap::from([(address(1), "0x8ac7230489e80000".to_string())]);
+
+        let changes = get_block_balance_changes(vec![], withdrawals, &balances).unwrap();
+        assert_eq!(changes.len(), 2);
+        let ether = |value: &Option<pbcodec::BigInt>| {
+            U256::from_big_endian(&value.as_ref().unwrap().bytes) / U256::exp10(18)
+        };
+        for change in &changes {
+            assert_eq!(change.reason, pbcodec::balance_change::Reason::Withdrawal as i32);
+            assert_eq!(change.address, prefix_hex::decode::<Vec<u8>>(address(1)).unwrap());
+        }
+        // 10 ether after both withdrawals
+        assert_eq!(ether(&changes[0].old_value), U256::from(7));
+        assert_eq!(ether(&changes[0].new_value), U256::from(8));
+        assert_eq!(ether(&changes[1].old_value), U256::from(8));
+        assert_eq!(ether(&changes[1].new_value), U256::from(10));
+
+        // withdrawals aren't converted without balances
+        let changes = get_block_balance_changes(vec![], vec![withdrawal(0, "0x3b9aca00")], &HashMap::new()).unwrap();
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn derive_tx_status() {
+        let mut root = trace(vec![]);
+        root.error = Some("Reverted".to_string());
+        let calls = build_calls(vec![root, trace(vec![0]), trace(vec![0, 0])]).unwrap();
+        assert!(calls.iter().all(|call| call.state_reverted));
+        assert!(calls[0].status_reverted && !calls[1].status_failed);
+
+        let status = |receipt_status, calls: &[pbcodec::Call]| {
+            pbcodec::TransactionTraceStatus::try_from(get_tx_trace_status(receipt_status, calls)).unwrap()
+        };
+        assert_eq!(status(Some(0), &calls), pbcodec::TransactionTraceStatus::Reverted);
+        assert_eq!(status(None, &calls), pbcodec::TransactionTraceStatus::Reverted);
+        assert_eq!(status(Some(0), &[]), pbcodec::TransactionTraceStatus::Failed);
+        assert_eq!(status(Some(1), &[]), pbcodec::TransactionTraceStatus::Succeeded);
+        assert_eq!(status(None, &[]), pbcodec::TransactionTraceStatus::Unknown);
+    }
+
+    #[test]
+    fn decode_revert_reasons() {
+        let encode = |selector: [u8; 4], token| [selector.to_vec(), abi::encode(&[token])].concat();
+        let error = encode(ERROR_SELECTOR, abi::Token::String("not enough".to_string()));
+        assert_eq!(decode_revert_reason(&error).unwrap(), "execution reverted: not enough");
+
+        let panic = encode(PANIC_SELECTOR, abi::Token::Uint(0x11.into()));
+        assert_eq!(
+            decode_revert_reason(&panic).unwrap(),
+            "execution reverted: arithmetic underflow or overflow (0x11)"
+        );
+
+        let custom = encode([0xe4, 0x50, 0xd3, 0x8c], abi::Token::Uint(1.into()));
+        assert_eq!(decode_revert_reason(&custom), None);
+        assert_eq!(decode_revert_reason(&[]), None);
+    }
+
+    #[test]
+    fn apply_chain_profiles() {
+        let tx = |r#type, from: u8| pbcodec::TransactionTrace {
+            r#type,
+            from: vec![from; 20],
+            to: vec![0; 20],
+            v: vec![0],
+            r: vec![0],
+            s: vec![0],
+            ..Default::default()
+        };
+        let block = |transaction_traces| pbcodec::Block {
+            transaction_traces,
+            ..Default::default()
+        };
+
+        let mut deposit = block(vec![tx(126, 1)]);
+        assert!(apply_chain_profile(&mut deposit, ChainProfile::Arbitrum).is_err());
+        // the default profile passes unknown types through as they are
+        apply_chain_profile(&mut deposit, ChainProfile::Ethereum).unwrap();
+        assert_eq!(deposit.transaction_traces[0].v, vec![0]);
+        apply_chain_profile(&mut deposit, ChainProfile::Optimism).unwrap();
+        assert!(deposit.transaction_traces[0].v.is_empty());
+
+        let mut state_sync = block(vec![tx(0, 0), tx(0, 1), tx(0x7f, 0)]);
+        assert!(apply_chain_profile(&mut state_sync, ChainProfile::Optimism).is_err());
+        apply_chain_profile(&mut state_sync, ChainProfile::Polygon).unwrap();
+        assert!(state_sync.transaction_traces[0].r.is_empty());
+        assert_eq!(state_sync.transaction_traces[1].r, vec![0]);
+        assert!(state_sync.transaction_traces[2].r.is_empty());
+
+        // zero signatures alone don't make a transaction a state sync one
+        let mut unsigned = block(vec![pbcodec::TransactionTrace {
+            to: vec![1; 20],
+            ..tx(0, 0)
+        }]);
+        apply_chain_profile(&mut unsigned, ChainProfile::Polygon).unwrap();
+        assert_eq!(unsigned.transaction_traces[0].r, vec![0]);
+    }
+
+    #[test]
+    fn convert_state_diffs() {
+        let diff = |key: &str, kind, prev: Option<&str>, next: Option<&str>| StateDiff {
+            transaction_index: 0,
+            address: address(1),
+            key: key.to_string(),
+            kind,
+            prev: prev.map(str::to_string),
+            next: next.map(str::to_string),
+        };
+        let slot = format!("0x{:064x}", 1);
+        let state_diffs = vec![
+            diff("balance", StateDiffKind::NoChange, None, None),
+            diff("nonce", StateDiffKind::Change, Some("0x1"), Some("0x2")),
+            diff("code", StateDiffKind::Add, None, Some("0x00")),
+            diff(&slot, StateDiffKind::Add, None, Some(&slot)),
+        ];
+        let mut calls = vec![pbcodec::Call::default()];
+        add_state_changes(&mut calls, state_diffs).unwrap();
+        let call = &calls[0];
+
+        assert!(call.balance_changes.is_empty());
+        assert_eq!((call.nonce_changes[0].old_value, call.nonce_changes[0].new_value), (1, 2));
+        assert_eq!(call.code_changes[0].new_code, vec![0]);
+        assert_eq!(
+            prefix_hex::encode(call.code_changes[0].old_hash.clone()),
+            "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+        assert_eq!(call.storage_changes[0].old_value, vec![0; 32]);
+        assert_eq!(call.storage_changes[0].new_value[31], 1);
+    }
+
+    #[tokio::test]
+    async fn report_missing_blocks() {
+        let chain = Chain((0..=5).map(|height| (height, format!("{:#x}", height))).collect());
+        let firehose = Firehose::new(Some(Arc::new(chain)), None, None, ConversionOptions::default(), 10).unwrap();
+
+        // the data source doesn't return a block it reports as finalized and the next one isn't finalized yet
+        for num in [3, 6] {
+            let request = SingleBlockRequest {
+                reference: Some(Reference::BlockNumber(BlockNumber { num })),
+                transforms: vec![],
+            };
+            let err = firehose.block(&request).await.unwrap_err();
+            assert!(matches!(err.downcast_ref::<BlockNotFound>(), Some(BlockNotFound(n)) if *n == num));
+        }
+    }
+}
@@ -0,0 +1,1023 @@
+use crate::datasource::{Block, DataRequest, HashAndHeight, HotUpdate, Log, LogRequest, Trace, Transaction};
+use crate::metrics::{REORGED_BLOCKS_COUNTER, REORGS_COUNTER};
+use crate::ds_rpc::{
+    backoff, check_replays, get_block_hash, get_state_diffs, get_stride, get_uncles, is_requested_trace, is_requested_tx, is_retryable, retry, split_range,
+    ConsistencyError, HeightTracker, Heights, StrideHints, MAX_RETRIES,
+};
+use anyhow::Context;
+use async_stream::try_stream;
+use ethers_core::types as evm;
+use ethers_providers::{Http, Middleware, Provider};
+use futures_core::Stream;
+use futures_util::future::join_all;
+use futures_util::StreamExt;
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, OnceCell};
+use tracing::{debug, error, info, warn};
+
+// number of chain updates a slow stream can fall behind before it has to resync
+const UPDATES_CAPACITY: usize = 64;
+
+/// Block with all its receipts and traces as returned by a node
+pub(crate) struct RawBlock {
+    header: evm::Block<evm::Transaction>,
+    transactions: Vec<evm::Transaction>,
+    receipts: Vec<evm::TransactionReceipt>,
+    // traces and state diffs are loaded only once some stream needs them
+    traces: OnceCell<Vec<evm::Trace>>,
+    replays: OnceCell<Vec<evm::BlockTrace>>,
+}
+
+impl RawBlock {
+    fn hash(&self) -> evm::H256 {
+        self.header.hash.expect("block hash is checked on fetch")
+    }
+
+    fn number(&self) -> u64 {
+        self.header.number.expect("block number is checked on fetch").as_u64()
+    }
+
+    fn parent(&self) -> HashAndHeight {
+        HashAndHeight {
+            hash: format!("{:?}", self.header.parent_hash),
+            height: self.number() - 1,
+        }
+    }
+
+    async fn traces(&self, client: &Provider<Http>) -> anyhow::Result<&Vec<evm::Trace>> {
+        self.traces
+            .get_or_try_init(|| async {
+                let hash = self.hash();
+                let traces = client.trace_block(self.number().into()).await?;
+                if traces.iter().any(|trace| trace.block_hash != hash) {
+                    Err(ConsistencyError::ForeignTraces(hash))?;
+                }
+                Ok(traces)
+            })
+            .await
+    }
+
+    async fn replays(&self, client: &Provider<Http>) -> anyhow::Result<&Vec<evm::BlockTrace>> {
+        self.replays
+            .get_or_try_init(|| async {
+                let replays = client
+                    .trace_replay_block_transactions(self.number().into(), vec![evm::TraceType::StateDiff])
+                    .await?;
+                let transactions: Vec<_> = self.transactions.iter().map(|tx| tx.hash).collect();
+                check_replays(self.hash(), &transactions, &replays)?;
+                Ok(replays)
+            })
+            .await
+    }
+}
+
+async fn get_block_receipts(
+    client: &Provider<Http>,
+    number: evm::U64,
+    transactions: &[evm::Transaction],
+) -> anyhow::Result<Vec<evm::TransactionReceipt>> {
+    if transactions.is_empty() {
+        return Ok(vec![]);
+    }
+
+    match client.get_block_receipts(number).await {
+        Ok(receipts) => Ok(receipts),
+        Err(e) => {
+            debug!("eth_getBlockReceipts failed, requesting receipts one by one: {}", e);
+            let futures: Vec<_> = transactions
+                .iter()
+                .map(|tx| async move {
+                    let receipt = client.get_transaction_receipt(tx.hash).await?;
+                    Ok(receipt.ok_or(ConsistencyError::ReceiptNotFound(tx.hash))?)
+                })
+                .collect();
+            join_all(futures).await.into_iter().collect()
+        }
+    }
+}
+
+async fn get_raw_block(client: &Provider<Http>, block_id: evm::BlockId) -> anyhow::Result<RawBlock> {
+    let mut header = client
+        .get_block_with_txs(block_id)
+        .await?
+        .ok_or(ConsistencyError::BlockNotFound(block_id))?;
+    let hash = header.hash.ok_or(ConsistencyError::MissingField("block hash"))?;
+    let number = header.number.ok_or(ConsistencyError::MissingField("block number"))?;
+    let transactions = std::mem::take(&mut header.transactions);
+
+    let receipts = get_block_receipts(client, number, &transactions).await?;
+    for receipt in &receipts {
+        if receipt.block_hash != Some(hash) {
+            Err(ConsistencyError::ForeignReceipt(receipt.transaction_hash))?;
+        }
+    }
+    let receipt_hashes: HashSet<_> = receipts.iter().map(|r| r.transaction_hash).collect();
+    for tx in &transactions {
+        if !receipt_hashes.contains(&tx.hash) {
+            Err(ConsistencyError::ReceiptNotFound(tx.hash))?;
+        }
+    }
+
+    Ok(RawBlock {
+        header,
+        transactions,
+        receipts,
+        traces: OnceCell::new(),
+        replays: OnceCell::new(),
+    })
+}
+
+fn is_requested_log(log: &evm::Log, requests: &[LogRequest]) -> bool {
+    let address = format!("{:?}", log.address);
+    let topic0 = log.topics.first().map(|topic| format!("{:?}", topic));
+    requests.iter().any(|request| {
+        let address_matches = request.address.is_empty() || request.address.contains(&address);
+        let topic_matches = request.topic0.is_empty()
+            || topic0.as_ref().is_some_and(|topic| request.topic0.contains(topic));
+        address_matches && topic_matches
+    })
+}
+
+async fn filter_block(
+    client: &Provider<Http>,
+    raw: &RawBlock,
+    request: &DataRequest,
+) -> anyhow::Result<Block> {
+    let mut block = Block::try_from(raw.header.clone())?;
+    if request.uncles {
+        block.uncles = get_uncles(client, &raw.header).await?;
+    }
+
+    let mut tx_hashes: HashSet<_> = raw
+        .transactions
+        .iter()
+        .filter(|tx| is_requested_tx(tx, &request.transactions))
+        .map(|tx| tx.hash)
+        .collect();
+    for receipt in &raw.receipts {
+        if receipt.logs.iter().any(|log| is_requested_log(log, &request.logs)) {
+            tx_hashes.insert(receipt.transaction_hash);
+        }
+    }
+    if !request.traces.is_empty() {
+        for trace in raw.traces(client).await? {
+            if let Some(tx_hash) = trace.transaction_hash {
+                if is_requested_trace(trace, &request.traces) {
+                    tx_hashes.insert(tx_hash);
+                }
+            }
+        }
+    }
+
+    if tx_hashes.is_empty() {
+        return Ok(block);
+    }
+
+    let receipts: HashMap<_, _> = raw
+        .receipts
+        .iter()
+        .map(|receipt| (receipt.transaction_hash, receipt))
+        .collect();
+    for tx in &raw.transactions {
+        if !tx_hashes.contains(&tx.hash) {
+            continue;
+        }
+        let receipt = *receipts
+            .get(&tx.hash)
+            .ok_or(ConsistencyError::ReceiptNotFound(tx.hash))?;
+        for log in &receipt.logs {
+            block.logs.push(Log::try_from(log.clone())?);
+        }
+        block.transactions.push(Transaction::try_from((tx.clone(), receipt.clone()))?);
+    }
+    block.logs.sort_by_key(|log| log.log_index);
+
+    block.traces = raw
+        .traces(client)
+        .await?
+        .iter()
+        .filter(|trace| trace.transaction_hash.is_some_and(|hash| tx_hashes.contains(&hash)))
+        .map(|trace| Trace::try_from(trace.clone()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if request.has_state_diffs() {
+        let transactions = raw.transactions.iter().filter(|tx| tx_hashes.contains(&tx.hash));
+        block.state_diffs = get_state_diffs(transactions, raw.replays(client).await?)?;
+    }
+
+    Ok(block)
+}
+
+fn get_height_updates(
+    height_tracker: Arc<HeightTracker>,
+    from: u64,
+) -> impl Stream<Item = anyhow::Result<Heights>> {
+    try_stream! {
+        let mut from = from;
+        loop {
+            let heights = height_tracker.wait(from).await?;
+            from = heights.head + 1;
+            yield heights;
+        }
+    }
+}
+
+/// Change of the canonical hot chain relative to the previous update
+pub(crate) struct ChainUpdate {
+    blocks: Vec<Arc<RawBlock>>,
+    base_head: HashAndHeight,
+    finalized_head: HashAndHeight,
+}
+
+// the follower either moves along the chain or stops for good
+type ChainEvent = Result<Arc<ChainUpdate>, ReorgError>;
+
+/// Canonical hot chain along with its blocks above the finalized one,
+/// they are kept here since the follower drops them once they get finalized
+struct ChainSnapshot {
+    chain: Vec<HashAndHeight>,
+    blocks: Vec<Arc<RawBlock>>,
+}
+
+struct FollowerState {
+    // canonical chain from the finalized block to the head
+    chain: Vec<HashAndHeight>,
+    // blocks above the finalized one including the ones which were forked out
+    blocks: HashMap<evm::H256, Arc<RawBlock>>,
+    updates: broadcast::Sender<ChainEvent>,
+    // the reason the follower has stopped if it can't follow the chain anymore
+    failure: Option<ReorgError>,
+}
+
+/// Process-wide tracker of the hot part of the chain.
+///
+/// It follows the chain head once and shares fetched blocks
+/// with all streams instead of letting each of them query the node on its own.
+pub(crate) struct ChainFollower {
+    state: Mutex<FollowerState>,
+}
+
+impl ChainFollower {
+    pub(crate) async fn start(
+        client: Provider<Http>,
+        height_tracker: Arc<HeightTracker>,
+        max_reorg_depth: u64,
+    ) -> anyhow::Result<Arc<ChainFollower>> {
+        let finalized = height_tracker.heights().await?.finalized;
+        let start = HashAndHeight {
+            hash: get_block_hash(&client, finalized).await?,
+            height: finalized,
+        };
+        let (updates, _) = broadcast::channel(UPDATES_CAPACITY);
+        let follower = Arc::new(ChainFollower {
+            state: Mutex::new(FollowerState {
+                chain: vec![start.clone()],
+                blocks: HashMap::new(),
+                updates,
+                failure: None,
+            }),
+        });
+        tokio::spawn(follower.clone().follow(client, height_tracker, start, max_reorg_depth));
+        Ok(follower)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FollowerState> {
+        self.state.lock().expect("follower state lock is poisoned")
+    }
+
+    fn get(&self, hash: &evm::H256) -> Option<Arc<RawBlock>> {
+        self.lock().blocks.get(hash).cloned()
+    }
+
+    fn subscribe(&self) -> anyhow::Result<(broadcast::Receiver<ChainEvent>, ChainSnapshot)> {
+        let state = self.lock();
+        let snapshot = Self::take_snapshot(&state)?;
+        Ok((state.updates.subscribe(), snapshot))
+    }
+
+    fn snapshot(&self) -> anyhow::Result<ChainSnapshot> {
+        Self::take_snapshot(&self.lock())
+    }
+
+    fn take_snapshot(state: &FollowerState) -> anyhow::Result<ChainSnapshot> {
+        if let Some(err) = &state.failure {
+            Err(err.clone())?;
+        }
+        let blocks = state.chain[1..]
+            .iter()
+            .map(|block| {
+                let hash = block.hash.parse::<evm::H256>()?;
+                state
+                    .blocks
+                    .get(&hash)
+                    .cloned()
+                    .with_context(|| format!("block {} isn't cached", block.hash))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(ChainSnapshot { chain: state.chain.clone(), blocks })
+    }
+
+    fn fail(&self, err: ReorgError) {
+        let mut state = self.lock();
+        state.failure = Some(err.clone());
+        let _ = state.updates.send(Err(err));
+    }
+
+    fn publish(&self, chain: &[HashAndHeight], update: HotUpdate) -> anyhow::Result<()> {
+        let mut state = self.lock();
+        let blocks = update
+            .blocks
+            .iter()
+            .map(|block| {
+                let hash = block.header.hash.parse::<evm::H256>()?;
+                state
+                    .blocks
+                    .get(&hash)
+                    .cloned()
+                    .with_context(|| format!("block {} isn't cached", block.header.hash))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let finalized = update.finalized_head.height;
+        state.chain = chain.to_vec();
+        state.blocks.retain(|_, block| block.number() >= finalized);
+        // there might be no active streams at the moment
+        let _ = state.updates.send(Ok(Arc::new(ChainUpdate {
+            blocks,
+            base_head: update.base_head,
+            finalized_head: update.finalized_head,
+        })));
+        Ok(())
+    }
+
+    async fn fetch(&self, client: &Provider<Http>, block_id: evm::BlockId) -> anyhow::Result<Arc<RawBlock>> {
+        if let evm::BlockId::Hash(hash) = block_id {
+            if let Some(block) = self.get(&hash) {
+                return Ok(block);
+            }
+        }
+        let block = Arc::new(get_raw_block(client, block_id).await?);
+        self.lock().blocks.insert(block.hash(), block.clone());
+        Ok(block)
+    }
+
+    async fn get_parent(&self, client: &Provider<Http>, block: &HashAndHeight) -> anyhow::Result<HashAndHeight> {
+        let hash = block.hash.parse::<evm::H256>()?;
+        if let Some(block) = self.get(&hash) {
+            return Ok(block.parent());
+        }
+        let header = client
+            .get_block(hash)
+            .await?
+            .ok_or(ConsistencyError::BlockNotFound(hash.into()))?;
+        Ok(HashAndHeight {
+            hash: format!("{:?}", header.parent_hash),
+            height: block.height - 1,
+        })
+    }
+
+    async fn follow(
+        self: Arc<Self>,
+        client: Provider<Http>,
+        height_tracker: Arc<HeightTracker>,
+        start: HashAndHeight,
+        max_reorg_depth: u64,
+    ) {
+        let mut nav = ForkNavigator::new(start, max_reorg_depth, |block_id| {
+            let client = client.clone();
+            let follower = self.clone();
+            async move {
+                let block = follower.fetch(&client, block_id).await?;
+                Block::try_from(block.header.clone())
+            }
+        });
+
+        loop {
+            let updates = get_height_updates(height_tracker.clone(), nav.get_height() + 1);
+            tokio::pin!(updates);
+
+            'updates: while let Some(result) = updates.next().await {
+                let heights = match result {
+                    Ok(heights) => heights,
+                    Err(err) => {
+                        warn!("failed to get chain height: {}", err);
+                        break;
+                    }
+                };
+
+                for number in nav.get_height() + 1..heights.head {
+                    let mut retries = 0;
+                    let update = loop {
+                        match nav.r#move(number, min(number, heights.finalized)).await {
+                            Ok(update) => break update,
+                            Err(err) if is_retryable(&err) && retries < MAX_RETRIES => {
+                                retries += 1;
+                                warn!("inconsistent rpc response, retrying: {}", err);
+                                backoff(retries).await;
+                            }
+                            Err(err) => match err.downcast::<ReorgError>() {
+                                Ok(err) => {
+                                    // the chain can't be followed without manual intervention,
+                                    // so the streams are terminated instead of being stalled
+                                    error!("failed to follow the chain: {}", err);
+                                    self.fail(err);
+                                    return;
+                                }
+                                Err(err) => {
+                                    warn!("failed to follow the chain: {}", err);
+                                    break 'updates;
+                                }
+                            },
+                        }
+                    };
+
+                    if let Err(err) = self.publish(&nav.chain, update) {
+                        warn!("failed to publish chain update: {}", err);
+                        break 'updates;
+                    }
+                }
+            }
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    /// Finds the latest block of the stream which is still a part of the canonical chain.
+    /// Returns None if the stream is ahead of the follower.
+    async fn find_base(
+        &self,
+        client: &Provider<Http>,
+        head: &HashAndHeight,
+        chain: &[HashAndHeight],
+    ) -> anyhow::Result<Option<HashAndHeight>> {
+        let first = &chain[0];
+        let last = chain.last().expect("chain state can't be empty");
+        if head.height > last.height {
+            return Ok(None);
+        }
+
+        let mut block = head.clone();
+        loop {
+            if block.height < first.height {
+                anyhow::bail!(
+                    "block {} isn't a descendant of the finalized block {}",
+                    head.hash,
+                    first.hash
+                );
+            }
+            let pos = usize::try_from(block.height - first.height)?;
+            if chain[pos].hash == block.hash {
+                return Ok(Some(block));
+            }
+            block = self.get_parent(client, &block).await?;
+        }
+    }
+}
+
+/// Streams hot blocks matching the request starting right after the given state
+pub(crate) fn get_hot_blocks(
+    follower: Arc<ChainFollower>,
+    client: Provider<Http>,
+    stride_hints: Arc<StrideHints>,
+    request: DataRequest,
+    state: HashAndHeight,
+) -> impl Stream<Item = anyhow::Result<HotUpdate>> {
+    try_stream! {
+        let (mut updates, snapshot) = follower.subscribe()?;
+        let mut snapshot = Some(snapshot);
+        let mut head = state;
+
+        loop {
+            if let Some(ChainSnapshot { chain, blocks: chain_blocks }) = snapshot.take() {
+                let finalized_head = chain[0].clone();
+
+                // the stream is behind the follower's finalized block so it has to be fetched separately,
+                // strides have every block even without requested data so the updates stay contiguous
+                if head.height < finalized_head.height {
+                    let stride = stride_hints.range_size(&request)?;
+                    for range in split_range(head.height + 1, finalized_head.height, stride) {
+                        let blocks = retry(|| get_stride(&client, &range, &request, &stride_hints)).await?;
+                        let new_head = blocks
+                            .last()
+                            .map(HashAndHeight::from)
+                            .with_context(|| format!("range {}-{} has no blocks", range.0, range.1))?;
+                        let update = HotUpdate {
+                            blocks,
+                            base_head: head.clone(),
+                            finalized_head: finalized_head.clone(),
+                        };
+                        head = new_head;
+                        yield update;
+                    }
+                    // the follower's hash of the finalized block is the one the chain is checked against
+                    head = finalized_head.clone();
+                }
+
+                if let Some(base_head) = follower.find_base(&client, &head, &chain).await? {
+                    let pos = usize::try_from(base_head.height - finalized_head.height)?;
+                    let mut blocks = vec![];
+                    for block in &chain_blocks[pos..] {
+                        blocks.push(retry(|| filter_block(&client, block, &request)).await?);
+                    }
+                    if !blocks.is_empty() || base_head != head {
+                        head = chain.last().expect("chain state can't be empty").clone();
+                        yield HotUpdate {
+                            blocks,
+                            base_head,
+                            finalized_head: finalized_head.clone(),
+                        };
+                    }
+                }
+
+                if let Some(to) = request.to {
+                    if finalized_head.height >= to {
+                        return
+                    }
+                }
+            }
+
+            let update = match updates.recv().await {
+                Ok(update) => update?,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("stream skipped {} chain updates, resyncing", skipped);
+                    snapshot = Some(follower.snapshot()?);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    Err(anyhow::anyhow!("chain follower has stopped"))?
+                }
+            };
+
+            if update.base_head != head {
+                // the stream is on another fork or ahead of the follower
+                snapshot = Some(follower.snapshot()?);
+                continue;
+            }
+
+            let mut blocks = vec![];
+            for block in &update.blocks {
+                blocks.push(retry(|| filter_block(&client, block, &request)).await?);
+            }
+            if let Some(block) = update.blocks.last() {
+                head = HashAndHeight {
+                    hash: format!("{:?}", block.hash()),
+                    height: block.number(),
+                };
+            }
+            let finalized_head = update.finalized_head.height;
+
+            yield HotUpdate {
+                blocks,
+                base_head: update.base_head.clone(),
+                finalized_head: update.finalized_head.clone(),
+            };
+
+            if let Some(to) = request.to {
+                if finalized_head >= to {
+                    return
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ReorgError {
+    /// The fork point is deeper than the configured limit
+    TooDeep { depth: u64, max_depth: u64 },
+    /// The new chain doesn't contain the block the navigator considers final
+    FinalizedBlockReplaced(HashAndHeight),
+}
+
+impl std::fmt::Display for ReorgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReorgError::TooDeep { depth, max_depth } => {
+                write!(f, "reorg depth exceeds {} blocks (at least {})", max_depth, depth)
+            }
+            ReorgError::FinalizedBlockReplaced(block) => {
+                write!(f, "finalized block {}#{} was replaced", block.height, block.hash)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReorgError {}
+
+struct ForkNavigator<C, F>
+where
+    C: Fn(evm::BlockId) -> F,
+    F: Future<Output = anyhow::Result<Block>>,
+{
+    chain: Vec<HashAndHeight>,
+    max_depth: u64,
+    get_block: C,
+}
+
+impl<C, F> ForkNavigator<C, F>
+where
+    C: Fn(evm::BlockId) -> F,
+    F: Future<Output = anyhow::Result<Block>>,
+{
+    pub fn new(state: HashAndHeight, max_depth: u64, get_block: C) -> ForkNavigator<C, F> {
+        ForkNavigator {
+            chain: vec![state],
+            max_depth,
+            get_block,
+        }
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.chain
+            .last()
+            .expect("chain state can't be empty")
+            .height
+    }
+
+    pub async fn r#move(&mut self, best: u64, finalized: u64) -> anyhow::Result<HotUpdate> {
+        let mut chain = self.chain.clone();
+        let old_head = chain.last().expect("chain state can't be empty").clone();
+        let mut new_blocks = vec![];
+        let mut depth = 0;
+
+        let best_head = if best > chain.last().unwrap().height {
+            let new_block = (self.get_block)(best.into()).await?;
+            let best_head = HashAndHeight {
+                hash: new_block.header.parent_hash.clone(),
+                height: new_block.header.number - 1,
+            };
+            new_blocks.push(new_block);
+            Some(best_head)
+        } else {
+            None
+        };
+
+        if let Some(mut best_head) = best_head {
+            while chain.last().unwrap().height < best_head.height {
+                let hash = best_head.hash.parse::<evm::H256>()?;
+                let block = (self.get_block)(hash.into()).await?;
+                best_head = HashAndHeight {
+                    hash: block.header.parent_hash.clone(),
+                    height: block.header.number - 1,
+                };
+                new_blocks.push(block);
+            }
+
+            while chain.last().unwrap().hash != best_head.hash {
+                depth += 1;
+                if depth > self.max_depth {
+                    return Err(ReorgError::TooDeep { depth, max_depth: self.max_depth }.into());
+                }
+                if chain.len() == 1 {
+                    return Err(ReorgError::FinalizedBlockReplaced(chain[0].clone()).into());
+                }
+                let hash = best_head.hash.parse::<evm::H256>()?;
+                let block = (self.get_block)(hash.into()).await?;
+                best_head = HashAndHeight {
+                    hash: block.header.parent_hash.clone(),
+                    height: block.header.number - 1,
+                };
+                new_blocks.push(block);
+                chain.pop();
+            }
+        }
+
+        new_blocks.reverse();
+        for block in &new_blocks {
+            chain.push(HashAndHeight {
+                hash: block.header.hash.clone(),
+                height: block.header.number,
+            });
+        }
+
+        // finality can't move back and can't get ahead of the blocks the navigator holds
+        if finalized > chain[0].height {
+            let finalized_pos = usize::try_from(finalized - chain[0].height)?;
+            chain.drain(..min(finalized_pos, chain.len() - 1));
+        }
+
+        self.chain = chain;
+
+        if depth > 0 {
+            let new_head = self.chain.last().expect("chain state can't be empty");
+            REORGS_COUNTER.inc();
+            REORGED_BLOCKS_COUNTER.inc_by(depth);
+            info!(
+                depth,
+                old_head = old_head.hash.as_str(),
+                old_height = old_head.height,
+                new_head = new_head.hash.as_str(),
+                new_height = new_head.height,
+                "chain reorg"
+            );
+        }
+
+        let base_head = if new_blocks.is_empty() {
+            self.chain.last().unwrap().clone()
+        } else {
+            HashAndHeight {
+                height: new_blocks[0].header.number - 1,
+                hash: new_blocks[0].header.parent_hash.clone(),
+            }
+        };
+
+        Ok(HotUpdate {
+            blocks: new_blocks,
+            base_head,
+            finalized_head: self.chain[0].clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_hot_blocks, ChainFollower, ForkNavigator, ReorgError};
+    use crate::datasource::{test_block, Block, DataRequest, HashAndHeight, HotUpdate, LogRequest, EMPTY_UNCLES_HASH};
+    use crate::ds_rpc::{Finality, HeightTracker, StrideHints};
+    use axum::extract::State;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use ethers_core::types as evm;
+    use ethers_providers::{Http, Provider};
+    use futures_util::StreamExt;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn block(number: u64, hash: evm::H256, parent_hash: evm::H256) -> Block {
+        test_block(number, format!("{:?}", hash), format!("{:?}", parent_hash))
+    }
+
+    // blocks 1..=10 of the main chain and 1..=10 of a fork which branches off the block 0
+    fn chains() -> (HashMap<u64, evm::H256>, HashMap<u64, evm::H256>) {
+        let main = (0..=10).map(|n| (n, evm::H256::from_low_u64_be(n + 1))).collect();
+        let fork = (0..=10)
+            .map(|n| (n, evm::H256::from_low_u64_be(if n == 0 { 1 } else { n + 1000 })))
+            .collect();
+        (main, fork)
+    }
+
+    fn get_block(chain: &HashMap<u64, evm::H256>, id: evm::BlockId) -> anyhow::Result<Block> {
+        let number = match id {
+            evm::BlockId::Number(evm::BlockNumber::Number(number)) => number.as_u64(),
+            evm::BlockId::Hash(hash) => *chain.iter().find(|(_, h)| **h == hash).unwrap().0,
+            _ => unreachable!(),
+        };
+        Ok(block(number, chain[&number], chain[&(number - 1)]))
+    }
+
+    fn head(chain: &HashMap<u64, evm::H256>, number: u64) -> HashAndHeight {
+        HashAndHeight {
+            hash: format!("{:?}", chain[&number]),
+            height: number,
+        }
+    }
+
+    // rpc node serving blocks of the given chain up to its head
+    struct Node {
+        head: u64,
+        chain: HashMap<u64, evm::H256>,
+        blocks: HashMap<evm::H256, Value>,
+    }
+
+    impl Node {
+        fn new(head: u64, chain: &HashMap<u64, evm::H256>) -> Node {
+            let mut node = Node { head, chain: HashMap::new(), blocks: HashMap::new() };
+            node.switch(head, chain);
+            node
+        }
+
+        fn switch(&mut self, head: u64, chain: &HashMap<u64, evm::H256>) {
+            for (number, hash) in chain {
+                let block = evm::Block::<evm::Transaction> {
+                    hash: Some(*hash),
+                    parent_hash: chain.get(&number.wrapping_sub(1)).copied().unwrap_or_default(),
+                    number: Some((*number).into()),
+                    author: Some(evm::Address::zero()),
+                    logs_bloom: Some(evm::Bloom::zero()),
+                    uncles_hash: EMPTY_UNCLES_HASH.parse().unwrap(),
+                    ..Default::default()
+                };
+                self.blocks.insert(*hash, serde_json::to_value(block).unwrap());
+            }
+            self.head = head;
+            self.chain = chain.clone();
+        }
+
+        fn call(&self, method: &str, params: &Value) -> Value {
+            match method {
+                "eth_blockNumber" => json!(format!("{:#x}", self.head)),
+                "eth_getBlockByNumber" => {
+                    let number = params[0].as_str().unwrap().trim_start_matches("0x");
+                    let number = u64::from_str_radix(number, 16).unwrap();
+                    self.chain
+                        .get(&number)
+                        .filter(|_| number <= self.head)
+                        .map(|hash| self.blocks[hash].clone())
+                        .unwrap_or(Value::Null)
+                }
+                "eth_getBlockByHash" => {
+                    let hash: evm::H256 = params[0].as_str().unwrap().parse().unwrap();
+                    self.blocks.get(&hash).cloned().unwrap_or(Value::Null)
+                }
+                "eth_getLogs" => json!([]),
+                _ => panic!("unexpected method {}", method),
+            }
+        }
+    }
+
+    async fn serve(node: Arc<Mutex<Node>>) -> Provider<Http> {
+        async fn handle(State(node): State<Arc<Mutex<Node>>>, Json(request): Json<Value>) -> Json<Value> {
+            let method = request["method"].as_str().unwrap();
+            let result = node.lock().unwrap().call(method, &request["params"]);
+            Json(json!({"jsonrpc": "2.0", "id": request["id"], "result": result}))
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let router = Router::new().route("/", post(handle)).with_state(node);
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        Provider::<Http>::try_from(url).unwrap()
+    }
+
+    async fn start(client: &Provider<Http>, confirmations: u64, max_reorg_depth: u64) -> Arc<ChainFollower> {
+        let finality = Finality::Confirmations(confirmations);
+        let height_tracker = Arc::new(HeightTracker::new(client.clone(), finality, Duration::from_millis(10)));
+        ChainFollower::start(client.clone(), height_tracker, max_reorg_depth)
+            .await
+            .unwrap()
+    }
+
+    fn request(from: u64) -> DataRequest {
+        DataRequest {
+            from,
+            to: None,
+            logs: vec![LogRequest {
+                address: vec![format!("{:?}", evm::Address::zero())],
+                ..Default::default()
+            }],
+            transactions: vec![],
+            traces: vec![],
+            uncles: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn catch_up_without_requested_data() {
+        let (main, _) = chains();
+        let node = Arc::new(Mutex::new(Node::new(10, &main)));
+        let client = serve(node).await;
+        let follower = start(&client, 3, 10).await;
+
+        // blocks 2..=7 are below the follower's finalized block and have no matching logs
+        let state = head(&main, 1);
+        let stream = get_hot_blocks(follower, client, Arc::new(StrideHints::default()), request(2), state.clone());
+        tokio::pin!(stream);
+
+        let mut last_head = state;
+        let mut updates: Vec<HotUpdate> = vec![];
+        while last_head.height < 9 {
+            let update = tokio::time::timeout(Duration::from_secs(10), stream.next())
+                .await
+                .expect("stream stalled")
+                .expect("stream ended")
+                .unwrap();
+            // every update continues right from the previous one
+            assert_eq!(update.base_head, last_head);
+            let numbers: Vec<_> = update.blocks.iter().map(|block| block.header.number).collect();
+            let expected: Vec<_> = (last_head.height + 1..).take(numbers.len()).collect();
+            assert_eq!(numbers, expected);
+            last_head = match update.blocks.last() {
+                Some(block) => head(&main, block.header.number),
+                None => update.base_head.clone(),
+            };
+            updates.push(update);
+        }
+
+        let hot = updates
+            .iter()
+            .find(|update| update.blocks.iter().any(|block| block.header.number > 7))
+            .unwrap();
+        assert_eq!(hot.finalized_head, head(&main, 7));
+        assert!(hot.base_head.height >= 7);
+        assert!(updates.iter().all(|update| update.finalized_head == head(&main, 7)));
+    }
+
+    #[tokio::test]
+    async fn terminate_streams_on_replaced_finalized_block() {
+        let (main, fork) = chains();
+        let node = Arc::new(Mutex::new(Node::new(10, &main)));
+        let client = serve(node.clone()).await;
+        let follower = start(&client, 3, 10).await;
+        let hints = Arc::new(StrideHints::default());
+
+        let stream = get_hot_blocks(follower.clone(), client.clone(), hints.clone(), request(8), head(&main, 7));
+        tokio::pin!(stream);
+        let mut height = 7;
+        while height < 9 {
+            let update = tokio::time::timeout(Duration::from_secs(10), stream.next())
+                .await
+                .expect("stream stalled")
+                .expect("stream ended")
+                .unwrap();
+            height = update.blocks.last().map_or(height, |block| block.header.number);
+        }
+
+        // the fork replaces all the blocks including the finalized one
+        node.lock().unwrap().switch(11, &fork);
+
+        let err = tokio::time::timeout(Duration::from_secs(10), stream.next())
+            .await
+            .expect("stream stalled")
+            .expect("stream ended")
+            .err()
+            .expect("stream should fail");
+        assert!(matches!(
+            err.downcast_ref::<ReorgError>(),
+            Some(ReorgError::FinalizedBlockReplaced(block)) if *block == head(&main, 7)
+        ));
+        assert!(stream.next().await.is_none());
+
+        // new streams fail right away
+        let stream = get_hot_blocks(follower, client, hints, request(8), head(&main, 7));
+        tokio::pin!(stream);
+        let err = stream.next().await.unwrap().err().expect("stream should fail");
+        assert!(err.downcast_ref::<ReorgError>().is_some());
+    }
+
+    #[tokio::test]
+    async fn limit_reorg_depth() {
+        let (main, fork) = chains();
+
+        let mut nav = ForkNavigator::new(head(&main, 0), 3, |id| {
+            let block = get_block(&main, id);
+            async move { block }
+        });
+        for number in 1..=5 {
+            nav.r#move(number, 0).await.unwrap();
+        }
+        let chain = nav.chain.clone();
+
+        let mut nav = ForkNavigator { chain: chain.clone(), max_depth: 5, get_block: |id| {
+            let block = get_block(&fork, id);
+            async move { block }
+        }};
+        let update = nav.r#move(6, 0).await.unwrap();
+        assert_eq!(update.base_head, head(&fork, 0));
+        assert_eq!(update.blocks.len(), 6);
+        assert_eq!(nav.chain.last(), Some(&head(&fork, 6)));
+
+        let mut nav = ForkNavigator {
+            chain,
+            max_depth: 4,
+            get_block: |id| {
+                let block = get_block(&fork, id);
+                async move { block }
+            },
+        };
+        let err = nav.r#move(6, 0).await.err().expect("move should fail");
+        assert!(matches!(
+            err.downcast_ref::<ReorgError>(),
+            Some(ReorgError::TooDeep { depth: 5, max_depth: 4 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn keep_finalized_block() {
+        let (main, fork) = chains();
+
+        let mut nav = ForkNavigator::new(head(&main, 0), 10, |id| {
+            let block = get_block(&main, id);
+            async move { block }
+        });
+        nav.r#move(1, 0).await.unwrap();
+        nav.r#move(2, 0).await.unwrap();
+
+        // finality is reported ahead of the navigator's head
+        let update = nav.r#move(3, 7).await.unwrap();
+        assert_eq!(update.finalized_head, head(&main, 3));
+        assert_eq!(nav.chain, vec![head(&main, 3)]);
+
+        // finality moved back
+        let update = nav.r#move(4, 1).await.unwrap();
+        assert_eq!(update.finalized_head, head(&main, 3));
+
+        let mut nav = ForkNavigator {
+            chain: nav.chain,
+            max_depth: 10,
+            get_block: |id| {
+                let block = get_block(&fork, id);
+                async move { block }
+            },
+        };
+        let err = nav.r#move(5, 3).await.err().expect("move should fail");
+        assert!(matches!(
+            err.downcast_ref::<ReorgError>(),
+            Some(ReorgError::FinalizedBlockReplaced(block)) if *block == head(&main, 3)
+        ));
+    }
+}
@@ -2,13 +2,16 @@ pub mod firehose;
 pub mod portal;
 pub mod ds_portal;
 pub mod ds_rpc;
+pub mod ds_validate;
 pub mod datasource;
 pub mod cursor;
 pub mod cli;
 pub mod metrics;
 pub mod stream;
 pub mod fetch;
+pub mod follower;
 pub mod logger;
+pub mod verify;
 
 #[path = "protobuf/sf.firehose.v2.rs"]
 pub mod pbfirehose;
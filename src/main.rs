@@ -6,14 +6,15 @@ use tracing::info;
 
 use firehose_grpc::ds_portal::PortalDataSource;
 use firehose_grpc::cli::Cli;
-use firehose_grpc::ds_rpc::RpcDataSource;
+use firehose_grpc::ds_rpc::{Finality, RpcDataSource};
+use firehose_grpc::ds_validate::{ValidatedDataSource, ValidationMode};
 use firehose_grpc::fetch::PortalFetch;
-use firehose_grpc::firehose::Firehose;
+use firehose_grpc::firehose::{ConversionOptions, Firehose};
 use firehose_grpc::pbfirehose::{fetch_server::FetchServer, stream_server::StreamServer};
 use firehose_grpc::stream::PortalStream;
 use firehose_grpc::metrics::start_prometheus_server;
 use firehose_grpc::portal::Portal;
-use firehose_grpc::datasource::HotDataSource;
+use firehose_grpc::datasource::{DataSource, HotDataSource};
 use firehose_grpc::logger;
 
 const FIREHOSE_DESCRIPTOR: &[u8] = tonic::include_file_descriptor_set!("firehose_descriptor");
@@ -24,21 +25,65 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let args = Cli::parse();
 
+    let validation_mode = if args.validation_mode.is_some() || args.verify_header_hashes {
+        Some(args.validation_mode.unwrap_or(ValidationMode::Fail))
+    } else {
+        None
+    };
+
     let rpc_ds: Option<Arc<dyn HotDataSource + Sync + Send>> = if let Some(rpc) = args.rpc {
-        let finality_confirmation = args
-            .finality_confirmation
-            .expect("finality_confirmation is required if rpc is specified");
-        Some(Arc::new(RpcDataSource::new(rpc, finality_confirmation)))
+        let finality = if let Some(tag) = args.finality_tag {
+            Finality::Tag(tag)
+        } else {
+            let finality_confirmation = args
+                .finality_confirmation
+                .expect("finality_confirmation or finality_tag is required if rpc is specified");
+            Finality::Confirmations(finality_confirmation)
+        };
+        let rpc_ds = Arc::new(RpcDataSource::new(rpc, finality, args.max_reorg_depth));
+        if let Some(mode) = validation_mode {
+            Some(Arc::new(ValidatedDataSource::new(rpc_ds, mode, args.verify_header_hashes)))
+        } else {
+            Some(rpc_ds)
+        }
+    } else {
+        None
+    };
+
+    let portal_ds: Option<Arc<dyn DataSource + Sync + Send>> = if let Some(portal) = args.portal {
+        let portal = Arc::new(Portal::new(portal));
+        let portal_ds = Arc::new(PortalDataSource::new(portal, args.chain_profile));
+        if let Some(mode) = validation_mode {
+            Some(Arc::new(ValidatedDataSource::new(portal_ds, mode, args.verify_header_hashes)))
+        } else {
+            Some(portal_ds)
+        }
     } else {
         None
     };
 
+    if portal_ds.is_none() && rpc_ds.is_none() {
+        return Err("either --portal or --rpc has to be specified".into());
+    }
+
     start_prometheus_server().await?;
     info!("prometheus metrics are available at 0.0.0.0:3000");
 
-    let portal = Arc::new(Portal::new(args.portal));
-    let portal_ds = Arc::new(PortalDataSource::new(portal));
-    let firehose = Arc::new(Firehose::new(portal_ds, rpc_ds));
+    let conversion = ConversionOptions {
+        strict_headers: args.strict_headers,
+        recover_public_keys: args.recover_public_keys,
+        chain_id: args.chain_id,
+        uncles: args.uncles,
+        chain: args.chain_profile,
+    };
+    let verify_roots = if args.verify_roots { args.chain_id } else { None };
+    let firehose = Arc::new(Firehose::new(
+        portal_ds,
+        rpc_ds,
+        verify_roots,
+        conversion,
+        args.max_reorg_depth,
+    )?);
 
     let stream_service = StreamServer::new(PortalStream::new(firehose.clone()));
     let fetch_service = FetchServer::new(PortalFetch::new(firehose));
@@ -1,4 +1,4 @@
-use prometheus::{opts, register_int_counter, register_int_gauge, IntCounter, IntGauge, TextEncoder, gather, Encoder};
+use prometheus::{opts, register_int_counter, register_int_counter_vec, register_int_gauge, IntCounter, IntCounterVec, IntGauge, TextEncoder, gather, Encoder};
 use axum::response::Response;
 use axum::http::header::CONTENT_TYPE;
 use axum::body::Body;
@@ -13,6 +13,28 @@ lazy_static::lazy_static! {
     pub static ref REQUESTS_COUNTER: IntCounter = register_int_counter!(
         opts!("firehose_requests_counter", "Request count")
     ).expect("Can't create a metric");
+    pub static ref REORGS_COUNTER: IntCounter = register_int_counter!(
+        opts!("firehose_reorgs_counter", "Number of chain reorgs")
+    ).expect("Can't create a metric");
+    pub static ref REORGED_BLOCKS_COUNTER: IntCounter = register_int_counter!(
+        opts!("firehose_reorged_blocks_counter", "Number of blocks replaced by chain reorgs")
+    ).expect("Can't create a metric");
+    pub static ref INVALID_BLOCKS_COUNTER: IntCounter = register_int_counter!(
+        opts!("firehose_invalid_blocks_counter", "Number of out of order or unlinked blocks returned by data sources")
+    ).expect("Can't create a metric");
+    pub static ref ROOT_VERIFICATIONS: IntCounterVec = register_int_counter_vec!(
+        opts!("firehose_root_verifications", "Transactions and receipts root verification results"),
+        &["root", "result"]
+    ).expect("Can't create a metric");
+    pub static ref HANDOFF_MISMATCHES_COUNTER: IntCounter = register_int_counter!(
+        opts!("firehose_handoff_mismatches_counter", "Number of chain mismatches between data sources")
+    ).expect("Can't create a metric");
+    pub static ref SENDER_MISMATCHES_COUNTER: IntCounter = register_int_counter!(
+        opts!("firehose_sender_mismatches_counter", "Number of transactions whose recovered sender differs from the from address")
+    ).expect("Can't create a metric");
+    pub static ref HANDOFF_UNDOS_COUNTER: IntCounter = register_int_counter!(
+        opts!("firehose_handoff_undos_counter", "Number of blocks undone after a chain mismatch between data sources")
+    ).expect("Can't create a metric");
 }
 
 pub async fn start_prometheus_server() -> anyhow::Result<()> {
@@ -24,7 +24,7 @@ impl Portal {
         let url = format!("{}/height", self.url);
         let response = self.client.get(url).send().await?;
 
-        if let Err(_) = response.error_for_status_ref() {
+        if response.error_for_status_ref().is_err() {
             let text = response.text().await?;
             anyhow::bail!("failed response from portal - {}", text);
         }
@@ -38,7 +38,7 @@ impl Portal {
         let url = format!("{}/stream", self.url);
         let response = self.client.post(url).json(query).send().await?;
 
-        if let Err(_) = response.error_for_status_ref() {
+        if response.error_for_status_ref().is_err() {
             let text = response.text().await?;
             anyhow::bail!("failed response from portal - {}", text);
         }
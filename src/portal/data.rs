@@ -7,22 +7,37 @@ pub struct BlockHeader {
     pub number: u64,
     pub hash: String,
     pub parent_hash: String,
-    pub size: u64,
-    pub sha3_uncles: String,
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub sha3_uncles: Option<String>,
     pub miner: String,
     pub state_root: String,
     pub transactions_root: String,
     pub receipts_root: String,
     pub logs_bloom: String,
     pub difficulty: String,
-    pub total_difficulty: String,
+    #[serde(default)]
+    pub total_difficulty: Option<String>,
     pub gas_limit: String,
     pub gas_used: String,
     pub timestamp: Number,
     pub extra_data: String,
-    pub mix_hash: String,
-    pub nonce: String,
+    #[serde(default)]
+    pub mix_hash: Option<String>,
+    #[serde(default)]
+    pub nonce: Option<String>,
     pub base_fee_per_gas: Option<String>,
+    #[serde(default)]
+    pub withdrawals_root: Option<String>,
+    #[serde(default)]
+    pub blob_gas_used: Option<String>,
+    #[serde(default)]
+    pub excess_blob_gas: Option<String>,
+    #[serde(default)]
+    pub parent_beacon_block_root: Option<String>,
+    #[serde(default)]
+    pub requests_hash: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -35,6 +50,24 @@ pub struct Log {
     pub transaction_index: u32,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessListItem {
+    pub address: String,
+    pub storage_keys: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Authorization {
+    pub chain_id: u64,
+    pub address: String,
+    pub nonce: u64,
+    pub y_parity: u8,
+    pub r: String,
+    pub s: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Transaction {
@@ -49,15 +82,38 @@ pub struct Transaction {
     pub gas_price: String,
     pub max_fee_per_gas: Option<String>,
     pub max_priority_fee_per_gas: Option<String>,
+    // system transactions of l2 chains aren't signed
+    #[serde(default)]
     pub v: String,
+    #[serde(default)]
     pub r: String,
+    #[serde(default)]
     pub s: String,
     pub y_parity: Option<u8>,
     pub gas_used: String,
     pub cumulative_gas_used: String,
     pub effective_gas_price: String,
     pub r#type: i32,
-    pub status: i32,
+    #[serde(default)]
+    pub status: Option<i32>,
+    #[serde(default)]
+    pub logs_bloom: Option<String>,
+    #[serde(default)]
+    pub access_list: Option<Vec<AccessListItem>>,
+    #[serde(default)]
+    pub max_fee_per_blob_gas: Option<String>,
+    #[serde(default)]
+    pub blob_versioned_hashes: Option<Vec<String>>,
+    #[serde(default)]
+    pub authorization_list: Option<Vec<Authorization>>,
+    #[serde(default)]
+    pub l1_fee: Option<String>,
+    #[serde(default)]
+    pub l1_gas_price: Option<String>,
+    #[serde(default)]
+    pub l1_gas_used: Option<String>,
+    #[serde(default)]
+    pub l1_fee_scalar: Option<Number>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -78,6 +134,22 @@ pub enum CallType {
     Staticcall,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum RewardType {
+    Block,
+    Uncle,
+    EmptyStep,
+    External,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ActionType {
+    Call(CallType),
+    Reward(RewardType),
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct TraceAction {
@@ -86,7 +158,17 @@ pub struct TraceAction {
     pub value: Option<String>,
     pub gas: Option<String>,
     pub input: Option<String>,
-    pub r#type: Option<CallType>,
+    pub r#type: Option<ActionType>,
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub refund_address: Option<String>,
+    #[serde(default)]
+    pub balance: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub init: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -95,12 +177,17 @@ pub struct TraceResult {
     pub gas_used: Option<String>,
     pub address: Option<String>,
     pub output: Option<String>,
+    #[serde(default)]
+    pub code: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Trace {
-    pub transaction_index: u32,
+    // reward traces don't belong to any transaction
+    pub transaction_index: Option<u32>,
+    #[serde(default)]
+    pub trace_address: Vec<u32>,
     pub r#type: TraceType,
     pub error: Option<String>,
     #[serde(default)]
@@ -109,10 +196,35 @@ pub struct Trace {
     pub result: Option<TraceResult>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub enum StateDiffKind {
+    #[serde(rename = "=")]
+    NoChange,
+    #[serde(rename = "+")]
+    Add,
+    #[serde(rename = "*")]
+    Change,
+    #[serde(rename = "-")]
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StateDiff {
+    pub transaction_index: u32,
+    pub address: String,
+    pub key: String,
+    pub kind: StateDiffKind,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Block {
     pub header: BlockHeader,
     pub logs: Option<Vec<Log>>,
     pub transactions: Option<Vec<Transaction>>,
     pub traces: Option<Vec<Trace>>,
+    #[serde(rename = "stateDiffs", default)]
+    pub state_diffs: Option<Vec<StateDiff>>,
 }
@@ -13,6 +13,8 @@ pub struct LogRequest {
     pub transaction_traces: bool,
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     pub transaction_logs: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub transaction_state_diffs: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -24,6 +26,8 @@ pub struct TxRequest {
     pub sighash: Vec<String>,
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     pub traces: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub state_diffs: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -38,9 +42,12 @@ pub struct TraceRequest {
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     pub transaction_logs: bool,
     #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub transaction_state_diffs: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
     pub parents: bool,
 }
 
+// portal blocks don't include withdrawals, they are served by rpc nodes only
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct BlockFieldSelection {
@@ -63,6 +70,11 @@ pub struct BlockFieldSelection {
     pub mix_hash: bool,
     pub base_fee_per_gas: bool,
     pub nonce: bool,
+    pub withdrawals_root: bool,
+    pub blob_gas_used: bool,
+    pub excess_blob_gas: bool,
+    pub parent_beacon_block_root: bool,
+    pub requests_hash: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -98,18 +110,35 @@ pub struct TxFieldSelection {
     pub effective_gas_price: bool,
     pub r#type: bool,
     pub status: bool,
+    pub logs_bloom: bool,
+    pub access_list: bool,
+    pub max_fee_per_blob_gas: bool,
+    pub blob_versioned_hashes: bool,
+    pub authorization_list: bool,
+    // only op stack datasets have l1 fee fields
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub l1_fee: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub l1_gas_price: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub l1_gas_used: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub l1_fee_scalar: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct TraceFieldSelection {
+    pub trace_address: bool,
     pub r#type: bool,
     pub error: bool,
     pub create_from: bool,
     pub create_value: bool,
     pub create_gas: bool,
+    pub create_init: bool,
     pub create_result_gas_used: bool,
     pub create_result_address: bool,
+    pub create_result_code: bool,
     pub call_from: bool,
     pub call_to: bool,
     pub call_value: bool,
@@ -118,9 +147,27 @@ pub struct TraceFieldSelection {
     pub call_type: bool,
     pub call_result_gas_used: bool,
     pub call_result_output: bool,
+    pub suicide_address: bool,
+    pub suicide_refund_address: bool,
+    pub suicide_balance: bool,
+    pub reward_author: bool,
+    pub reward_value: bool,
+    pub reward_type: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StateDiffFieldSelection {
+    pub transaction_index: bool,
+    pub address: bool,
+    pub key: bool,
+    pub kind: bool,
+    pub prev: bool,
+    pub next: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct FieldSelection {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub block: Option<BlockFieldSelection>,
@@ -130,6 +177,8 @@ pub struct FieldSelection {
     pub transaction: Option<TxFieldSelection>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub trace: Option<TraceFieldSelection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_diff: Option<StateDiffFieldSelection>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
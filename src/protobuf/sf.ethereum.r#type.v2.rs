@@ -155,6 +155,25 @@ pub struct BlockHeader {
     /// Base fee per gas according to EIP-1559 (e.g. London Fork) rules, only set if London is present/active on the chain.
     #[prost(message, optional, tag = "18")]
     pub base_fee_per_gas: ::core::option::Option<BigInt>,
+    /// Withdrawals root hash according to EIP-4895 (e.g. Shanghai Fork) rules, only set if Shanghai is present/active on the chain.
+    #[prost(bytes = "vec", tag = "19")]
+    pub withdrawals_root: ::prost::alloc::vec::Vec<u8>,
+    /// BlobGasUsed is the total amount of blob gas consumed by the transactions of the block according to EIP-4844
+    /// (e.g. Cancun Fork) rules, only set if Cancun is present/active on the chain.
+    #[prost(uint64, optional, tag = "22")]
+    pub blob_gas_used: ::core::option::Option<u64>,
+    /// ExcessBlobGas is the running total of blob gas consumed in excess of the target according to EIP-4844
+    /// (e.g. Cancun Fork) rules, only set if Cancun is present/active on the chain.
+    #[prost(uint64, optional, tag = "23")]
+    pub excess_blob_gas: ::core::option::Option<u64>,
+    /// ParentBeaconRoot is the root of the parent beacon block according to EIP-4788 (e.g. Cancun Fork) rules,
+    /// only set if Cancun is present/active on the chain.
+    #[prost(bytes = "vec", tag = "24")]
+    pub parent_beacon_root: ::prost::alloc::vec::Vec<u8>,
+    /// RequestsHash is the hash of the execution layer requests according to EIP-7685 (e.g. Prague Fork) rules,
+    /// only set if Prague is present/active on the chain.
+    #[prost(bytes = "vec", tag = "25")]
+    pub requests_hash: ::prost::alloc::vec::Vec<u8>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct BigInt {
@@ -246,6 +265,28 @@ pub struct TransactionTrace {
     pub receipt: ::core::option::Option<TransactionReceipt>,
     #[prost(message, repeated, tag = "32")]
     pub calls: ::prost::alloc::vec::Vec<Call>,
+    /// BlobGas is the amount of blob gas the transaction pays for, it equals to `len(blob_hashes) * 131072`.
+    ///
+    /// This will is populated only if `TransactionTrace.Type == TRX_TYPE_BLOB` which is possible only
+    /// if Cancun fork is active on the chain.
+    #[prost(uint64, optional, tag = "33")]
+    pub blob_gas: ::core::option::Option<u64>,
+    /// BlobGasFeeCap is the maximum fee per blob gas the user is willing to pay for the blob gas used.
+    ///
+    /// This will is populated only if `TransactionTrace.Type == TRX_TYPE_BLOB`.
+    #[prost(message, optional, tag = "34")]
+    pub blob_gas_fee_cap: ::core::option::Option<BigInt>,
+    /// BlobHashes are the versioned hashes of the blobs the transaction carries.
+    ///
+    /// This will is populated only if `TransactionTrace.Type == TRX_TYPE_BLOB`.
+    #[prost(bytes = "vec", repeated, tag = "35")]
+    pub blob_hashes: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    /// SetCodeAuthorizations is the list of authorizations the transaction applies.
+    ///
+    /// This will is populated only if `TransactionTrace.Type == TRX_TYPE_SET_CODE` which is possible only
+    /// if Prague fork is active on the chain.
+    #[prost(message, repeated, tag = "36")]
+    pub set_code_authorizations: ::prost::alloc::vec::Vec<SetCodeAuthorization>,
 }
 /// Nested message and enum types in `TransactionTrace`.
 pub mod transaction_trace {
@@ -273,6 +314,26 @@ pub mod transaction_trace {
         /// max base gas gee and max priority gas fee to pay for this transaction. Transaction's of those type are
         /// executed against EIP-1559 rules which dictates a dynamic gas cost based on the congestion of the network.
         TrxTypeDynamicFee = 2,
+        /// Transaction which carries blobs of data which are available for a limited time. The blobs
+        /// are referenced by their versioned hashes and are paid with a separate blob gas fee.
+        ///
+        /// Added in Cancun fork (EIP-4844).
+        TrxTypeBlob = 3,
+        /// Transaction which allows externally owned accounts to set the code of their account to
+        /// the code of a delegated contract through a list of signed authorizations.
+        ///
+        /// Added in Prague fork (EIP-7702).
+        TrxTypeSetCode = 4,
+        /// Arbitrum-specific transactions
+        TrxTypeArbitrumDeposit = 100,
+        TrxTypeArbitrumUnsigned = 101,
+        TrxTypeArbitrumContract = 102,
+        TrxTypeArbitrumRetry = 104,
+        TrxTypeArbitrumSubmitRetryable = 105,
+        TrxTypeArbitrumInternal = 106,
+        TrxTypeArbitrumLegacy = 120,
+        /// OPTIMISM-specific transactions
+        TrxTypeOptimismDeposit = 126,
     }
     impl Type {
         /// String value of the enum field names used in the ProtoBuf definition.
@@ -284,6 +345,18 @@ pub mod transaction_trace {
                 Self::TrxTypeLegacy => "TRX_TYPE_LEGACY",
                 Self::TrxTypeAccessList => "TRX_TYPE_ACCESS_LIST",
                 Self::TrxTypeDynamicFee => "TRX_TYPE_DYNAMIC_FEE",
+                Self::TrxTypeBlob => "TRX_TYPE_BLOB",
+                Self::TrxTypeSetCode => "TRX_TYPE_SET_CODE",
+                Self::TrxTypeArbitrumDeposit => "TRX_TYPE_ARBITRUM_DEPOSIT",
+                Self::TrxTypeArbitrumUnsigned => "TRX_TYPE_ARBITRUM_UNSIGNED",
+                Self::TrxTypeArbitrumContract => "TRX_TYPE_ARBITRUM_CONTRACT",
+                Self::TrxTypeArbitrumRetry => "TRX_TYPE_ARBITRUM_RETRY",
+                Self::TrxTypeArbitrumSubmitRetryable => {
+                    "TRX_TYPE_ARBITRUM_SUBMIT_RETRYABLE"
+                }
+                Self::TrxTypeArbitrumInternal => "TRX_TYPE_ARBITRUM_INTERNAL",
+                Self::TrxTypeArbitrumLegacy => "TRX_TYPE_ARBITRUM_LEGACY",
+                Self::TrxTypeOptimismDeposit => "TRX_TYPE_OPTIMISM_DEPOSIT",
             }
         }
         /// Creates an enum from field names used in the ProtoBuf definition.
@@ -292,11 +365,46 @@ pub mod transaction_trace {
                 "TRX_TYPE_LEGACY" => Some(Self::TrxTypeLegacy),
                 "TRX_TYPE_ACCESS_LIST" => Some(Self::TrxTypeAccessList),
                 "TRX_TYPE_DYNAMIC_FEE" => Some(Self::TrxTypeDynamicFee),
+                "TRX_TYPE_BLOB" => Some(Self::TrxTypeBlob),
+                "TRX_TYPE_SET_CODE" => Some(Self::TrxTypeSetCode),
+                "TRX_TYPE_ARBITRUM_DEPOSIT" => Some(Self::TrxTypeArbitrumDeposit),
+                "TRX_TYPE_ARBITRUM_UNSIGNED" => Some(Self::TrxTypeArbitrumUnsigned),
+                "TRX_TYPE_ARBITRUM_CONTRACT" => Some(Self::TrxTypeArbitrumContract),
+                "TRX_TYPE_ARBITRUM_RETRY" => Some(Self::TrxTypeArbitrumRetry),
+                "TRX_TYPE_ARBITRUM_SUBMIT_RETRYABLE" => {
+                    Some(Self::TrxTypeArbitrumSubmitRetryable)
+                }
+                "TRX_TYPE_ARBITRUM_INTERNAL" => Some(Self::TrxTypeArbitrumInternal),
+                "TRX_TYPE_ARBITRUM_LEGACY" => Some(Self::TrxTypeArbitrumLegacy),
+                "TRX_TYPE_OPTIMISM_DEPOSIT" => Some(Self::TrxTypeOptimismDeposit),
                 _ => None,
             }
         }
     }
 }
+/// SetCodeAuthorization is a signed permission of an account to set its code to the code
+/// of the given address (EIP-7702).
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SetCodeAuthorization {
+    /// Discarded is set when the authorization was invalid and wasn't applied.
+    #[prost(bool, tag = "1")]
+    pub discarded: bool,
+    #[prost(bytes = "vec", tag = "2")]
+    pub chain_id: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub address: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "4")]
+    pub nonce: u64,
+    #[prost(uint32, tag = "5")]
+    pub v: u32,
+    #[prost(bytes = "vec", tag = "6")]
+    pub r: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "7")]
+    pub s: ::prost::alloc::vec::Vec<u8>,
+    /// Authority is the account which signed the authorization if it could be recovered.
+    #[prost(bytes = "vec", optional, tag = "8")]
+    pub authority: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+}
 /// AccessTuple represents a list of storage keys for a given contract's address and is used
 /// for AccessList construction.
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -337,6 +445,17 @@ pub struct TransactionReceipt {
     pub logs_bloom: ::prost::alloc::vec::Vec<u8>,
     #[prost(message, repeated, tag = "4")]
     pub logs: ::prost::alloc::vec::Vec<Log>,
+    /// L1 data fee fields of OP stack receipts, they aren't set for other chains and for deposit
+    /// transactions. Upstream doesn't define them, so their tags are kept away from the ones
+    /// it might use for its own additions.
+    #[prost(message, optional, tag = "100")]
+    pub l1_fee: ::core::option::Option<BigInt>,
+    #[prost(message, optional, tag = "101")]
+    pub l1_gas_price: ::core::option::Option<BigInt>,
+    #[prost(uint64, optional, tag = "102")]
+    pub l1_gas_used: ::core::option::Option<u64>,
+    #[prost(string, optional, tag = "103")]
+    pub l1_fee_scalar: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Log {
@@ -526,6 +645,8 @@ pub mod balance_change {
         CallBalanceOverride = 12,
         /// Used on chain(s) where some Ether burning happens
         Burn = 15,
+        /// Used for the beacon chain withdrawals (EIP-4895)
+        Withdrawal = 16,
     }
     impl Reason {
         /// String value of the enum field names used in the ProtoBuf definition.
@@ -550,6 +671,7 @@ pub mod balance_change {
                 Self::SuicideWithdraw => "REASON_SUICIDE_WITHDRAW",
                 Self::CallBalanceOverride => "REASON_CALL_BALANCE_OVERRIDE",
                 Self::Burn => "REASON_BURN",
+                Self::Withdrawal => "REASON_WITHDRAWAL",
             }
         }
         /// Creates an enum from field names used in the ProtoBuf definition.
@@ -571,6 +693,7 @@ pub mod balance_change {
                 "REASON_SUICIDE_WITHDRAW" => Some(Self::SuicideWithdraw),
                 "REASON_CALL_BALANCE_OVERRIDE" => Some(Self::CallBalanceOverride),
                 "REASON_BURN" => Some(Self::Burn),
+                "REASON_WITHDRAWAL" => Some(Self::Withdrawal),
                 _ => None,
             }
         }
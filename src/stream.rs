@@ -1,4 +1,5 @@
-use crate::firehose::Firehose;
+use crate::ds_rpc::is_retryable;
+use crate::firehose::{BlockNotFound, Firehose};
 use crate::pbfirehose::{stream_server::Stream, Request, Response};
 use crate::metrics;
 use futures_util::stream::StreamExt;
@@ -16,6 +17,18 @@ impl PortalStream {
     }
 }
 
+// clients are expected to reconnect on unavailable, other errors won't go away by retrying
+pub(crate) fn to_status(err: &anyhow::Error) -> tonic::Status {
+    let message = format!("{:#}", err);
+    if err.downcast_ref::<BlockNotFound>().is_some() {
+        tonic::Status::not_found(message)
+    } else if is_retryable(err) {
+        tonic::Status::unavailable(message)
+    } else {
+        tonic::Status::internal(message)
+    }
+}
+
 #[tonic::async_trait]
 impl Stream for PortalStream {
     type BlocksStream = ReceiverStream<Result<Response, tonic::Status>>;
@@ -35,6 +48,7 @@ impl Stream for PortalStream {
                 Ok(stream) => stream,
                 Err(e) => {
                     error!("failed to establish block stream: {}", e);
+                    let _ = tx.send(Err(to_status(&e))).await;
                     return;
                 }
             };
@@ -55,6 +69,8 @@ impl Stream for PortalStream {
                     }
                     Err(e) => {
                         error!("error while streaming data: {}", e);
+                        // let the client know the stream is broken instead of just closing it
+                        let _ = tx.send(Err(to_status(&e))).await;
                         metrics::ACTIVE_REQUESTS.dec();
                         return;
                     }
@@ -68,3 +84,23 @@ impl Stream for PortalStream {
         Ok(tonic::Response::new(ReceiverStream::new(rx)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::to_status;
+    use crate::ds_rpc::ConsistencyError;
+
+    #[test]
+    fn keep_error_text_in_status() {
+        let err = anyhow::Error::new(ConsistencyError::MissingField("hash"))
+            .context("failed to fetch blocks");
+        let status = to_status(&err);
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(status.message(), "failed to fetch blocks: hash is missing in the response");
+
+        let err = anyhow::anyhow!("invalid request").context("stream failed");
+        let status = to_status(&err);
+        assert_eq!(status.code(), tonic::Code::Internal);
+        assert_eq!(status.message(), "stream failed: invalid request");
+    }
+}
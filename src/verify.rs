@@ -0,0 +1,757 @@
+use crate::datasource::BlockHeader;
+use crate::metrics::ROOT_VERIFICATIONS;
+use crate::pbcodec;
+use crate::firehose::ChainProfile;
+use anyhow::{format_err, Context};
+use ethers_core::abi::ethereum_types::BloomInput;
+use ethers_core::k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use ethers_core::types as evm;
+use ethers_core::utils::keccak256;
+use ethers_core::utils::rlp::{self, RlpStream};
+
+/// Header layout which depends on the fields introduced by hard forks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fork {
+    Frontier,
+    London,
+    Shanghai,
+    Cancun,
+    Prague,
+}
+
+#[derive(Debug)]
+pub enum VerificationError {
+    HashMismatch {
+        number: u64,
+        fork: Fork,
+        hash: String,
+        computed: String,
+    },
+    RootMismatch {
+        number: u64,
+        root: &'static str,
+        expected: String,
+        computed: String,
+    },
+    MissingField {
+        number: u64,
+        field: &'static str,
+    },
+}
+
+impl std::fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerificationError::HashMismatch { number, fork, hash, computed } => write!(
+                f,
+                "block {} has hash {} but its {:?} header hashes to {}",
+                number, hash, fork, computed
+            ),
+            VerificationError::RootMismatch { number, root, expected, computed } => write!(
+                f,
+                "block {} has {} root {} but its data hashes to {}",
+                number, root, expected, computed
+            ),
+            VerificationError::MissingField { number, field } => {
+                write!(f, "block {} has no {}", number, field)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerificationError {}
+
+fn qty(label: &'static str, value: &str) -> anyhow::Result<evm::U256> {
+    evm::U256::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|_| format_err!("invalid {}: {}", label, value))
+}
+
+fn hash(label: &'static str, value: &str) -> anyhow::Result<evm::H256> {
+    value.parse().map_err(|_| format_err!("invalid {}: {}", label, value))
+}
+
+/// Determines the header layout from the optional fields which are present
+pub fn get_fork(header: &BlockHeader) -> anyhow::Result<Fork> {
+    let fields = [
+        header.base_fee_per_gas.is_some(),
+        header.withdrawals_root.is_some(),
+        header.blob_gas_used.is_some(),
+        header.excess_blob_gas.is_some(),
+        header.parent_beacon_block_root.is_some(),
+        header.requests_hash.is_some(),
+    ];
+    let present = fields.iter().take_while(|present| **present).count();
+    if fields[present..].iter().any(|present| *present) {
+        anyhow::bail!("block {} has an unexpected set of header fields", header.number)
+    }
+    match present {
+        0 => Ok(Fork::Frontier),
+        1 => Ok(Fork::London),
+        2 => Ok(Fork::Shanghai),
+        5 => Ok(Fork::Cancun),
+        6 => Ok(Fork::Prague),
+        _ => anyhow::bail!("block {} has incomplete blob gas fields", header.number),
+    }
+}
+
+/// Checks that the header has all fields which canonical Ethereum blocks have.
+/// The total difficulty isn't checked since nodes stopped serving it after the merge.
+pub fn check_required_fields(header: &BlockHeader) -> Result<(), VerificationError> {
+    let fields = [
+        ("size", header.size.is_some()),
+        ("sha3 uncles", header.sha3_uncles.is_some()),
+        ("mix hash", header.mix_hash.is_some()),
+        ("nonce", header.nonce.is_some()),
+    ];
+    match fields.iter().find(|(_, present)| !present) {
+        Some((field, _)) => Err(VerificationError::MissingField { number: header.number, field }),
+        None => Ok(()),
+    }
+}
+
+/// Computes the keccak hash of the RLP encoded header
+pub fn compute_hash(header: &BlockHeader) -> anyhow::Result<evm::H256> {
+    check_required_fields(header)?;
+    let sha3_uncles = header.sha3_uncles.as_deref().unwrap_or_default();
+    let mix_hash = header.mix_hash.as_deref().unwrap_or_default();
+    let nonce = header.nonce.as_deref().unwrap_or_default();
+
+    let mut stream = RlpStream::new();
+    stream.begin_unbounded_list();
+    stream.append(&hash("parent hash", &header.parent_hash)?);
+    stream.append(&hash("sha3 uncles", sha3_uncles)?);
+    stream.append(
+        &header
+            .miner
+            .parse::<evm::Address>()
+            .map_err(|_| format_err!("invalid miner: {}", header.miner))?,
+    );
+    stream.append(&hash("state root", &header.state_root)?);
+    stream.append(&hash("transactions root", &header.transactions_root)?);
+    stream.append(&hash("receipts root", &header.receipts_root)?);
+    stream.append(
+        &header
+            .logs_bloom
+            .parse::<evm::Bloom>()
+            .map_err(|_| format_err!("invalid logs bloom: {}", header.logs_bloom))?,
+    );
+    stream.append(&qty("difficulty", &header.difficulty)?);
+    stream.append(&header.number);
+    stream.append(&qty("gas limit", &header.gas_limit)?);
+    stream.append(&qty("gas used", &header.gas_used)?);
+    stream.append(&header.timestamp);
+    stream.append(
+        &prefix_hex::decode::<Vec<u8>>(&header.extra_data)
+            .map_err(|_| format_err!("invalid extra data: {}", header.extra_data))?,
+    );
+    stream.append(&hash("mix hash", mix_hash)?);
+    stream.append(
+        &nonce
+            .parse::<evm::H64>()
+            .map_err(|_| format_err!("invalid nonce: {}", nonce))?,
+    );
+
+    if let Some(value) = &header.base_fee_per_gas {
+        stream.append(&qty("base fee per gas", value)?);
+    }
+    if let Some(value) = &header.withdrawals_root {
+        stream.append(&hash("withdrawals root", value)?);
+    }
+    if let Some(value) = &header.blob_gas_used {
+        stream.append(&qty("blob gas used", value)?);
+    }
+    if let Some(value) = &header.excess_blob_gas {
+        stream.append(&qty("excess blob gas", value)?);
+    }
+    if let Some(value) = &header.parent_beacon_block_root {
+        stream.append(&hash("parent beacon block root", value)?);
+    }
+    if let Some(value) = &header.requests_hash {
+        stream.append(&hash("requests hash", value)?);
+    }
+    stream.finalize_unbounded_list();
+
+    Ok(keccak256(stream.out()).into())
+}
+
+/// Checks that the header hash matches its content
+pub fn verify_header_hash(header: &BlockHeader) -> anyhow::Result<()> {
+    let fork = get_fork(header)?;
+    let computed = compute_hash(header)
+        .with_context(|| format!("failed to encode header of block {}", header.number))?;
+    if computed != hash("block hash", &header.hash)? {
+        return Err(VerificationError::HashMismatch {
+            number: header.number,
+            fork,
+            hash: header.hash.clone(),
+            computed: format!("{:?}", computed),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Keccak hash of the RLP encoded empty string, i.e. the root of an empty trie
+const EMPTY_ROOT: &str = "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421";
+
+fn to_nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect()
+}
+
+fn hex_prefix(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 };
+    let mut encoded = vec![];
+    let rest = if nibbles.len() % 2 == 1 {
+        encoded.push(((flag + 1) << 4) | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        encoded.push(flag << 4);
+        nibbles
+    };
+    encoded.extend(rest.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    encoded
+}
+
+// nodes shorter than a hash are embedded into their parents
+fn append_node(stream: &mut RlpStream, node: Vec<u8>) {
+    if node.len() < 32 {
+        stream.append_raw(&node, 1);
+    } else {
+        stream.append(&keccak256(&node).to_vec());
+    }
+}
+
+/// Encodes a trie node for items sorted by their nibble keys which share the first `depth` nibbles
+fn encode_node(items: &[(Vec<u8>, Vec<u8>)], depth: usize) -> Vec<u8> {
+    let mut stream = RlpStream::new();
+    let first = &items[0].0[depth..];
+    if items.len() == 1 {
+        stream.begin_list(2);
+        stream.append(&hex_prefix(first, true));
+        stream.append(&items[0].1);
+        return stream.out().to_vec();
+    }
+
+    let last = &items[items.len() - 1].0[depth..];
+    let prefix = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+    if prefix > 0 {
+        stream.begin_list(2);
+        stream.append(&hex_prefix(&first[..prefix], false));
+        append_node(&mut stream, encode_node(items, depth + prefix));
+        return stream.out().to_vec();
+    }
+
+    stream.begin_list(17);
+    let mut value = None;
+    let mut rest = items;
+    if rest[0].0.len() == depth {
+        value = Some(&rest[0].1);
+        rest = &rest[1..];
+    }
+    for nibble in 0..16 {
+        let len = rest.iter().take_while(|(key, _)| key[depth] == nibble).count();
+        if len == 0 {
+            stream.append_empty_data();
+        } else {
+            append_node(&mut stream, encode_node(&rest[..len], depth + 1));
+        }
+        rest = &rest[len..];
+    }
+    match value {
+        Some(value) => stream.append(value),
+        None => stream.append_empty_data(),
+    };
+    stream.out().to_vec()
+}
+
+/// Computes the root of a Merkle Patricia trie with the given items
+pub fn trie_root(items: Vec<(Vec<u8>, Vec<u8>)>) -> evm::H256 {
+    if items.is_empty() {
+        return EMPTY_ROOT.parse().expect("valid hash");
+    }
+    let mut items: Vec<_> = items
+        .into_iter()
+        .map(|(key, value)| (to_nibbles(&key), value))
+        .collect();
+    items.sort();
+    keccak256(encode_node(&items, 0)).into()
+}
+
+/// Computes the root of a trie where items are keyed by their RLP encoded position
+pub fn ordered_trie_root(values: Vec<Vec<u8>>) -> evm::H256 {
+    let items = values
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| (rlp::encode(&index).to_vec(), value))
+        .collect();
+    trie_root(items)
+}
+
+// quantities are encoded as big endian bytes without leading zeros
+fn uint(bytes: &[u8]) -> &[u8] {
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    &bytes[zeros..]
+}
+
+fn big_int(value: &Option<pbcodec::BigInt>) -> &[u8] {
+    value.as_ref().map_or(&[], |value| uint(&value.bytes))
+}
+
+fn append_access_list(stream: &mut RlpStream, access_list: &[pbcodec::AccessTuple]) {
+    stream.begin_list(access_list.len());
+    for tuple in access_list {
+        stream.begin_list(2);
+        stream.append(&tuple.address);
+        stream.begin_list(tuple.storage_keys.len());
+        for key in &tuple.storage_keys {
+            stream.append(key);
+        }
+    }
+}
+
+// encodes the transaction fields followed by the signature if it's signed.
+// unsigned legacy transactions include the EIP-155 chain id if it's known.
+fn encode_fields(tx: &pbcodec::TransactionTrace, chain_id: Option<u64>, signed: bool) -> Option<Vec<u8>> {
+    // contract creations are converted with the zero address as a recipient
+    let is_create = uint(&tx.to).is_empty()
+        && tx.calls.first().map_or(true, |call| call.call_type == pbcodec::CallType::Create as i32);
+    let to: &[u8] = if is_create { &[] } else { &tx.to };
+
+    let fields = match tx.r#type {
+        0 if !signed && chain_id.is_some() => 9,
+        0 => 6,
+        1 => 8,
+        2 => 9,
+        3 => 11,
+        4 => 10,
+        _ => return None,
+    };
+    let mut stream = RlpStream::new();
+    stream.begin_list(if signed { fields + 3 } else { fields });
+    match tx.r#type {
+        0 => {
+            stream.append(&tx.nonce);
+            stream.append(&big_int(&tx.gas_price));
+        }
+        1 => {
+            stream.append(&chain_id?);
+            stream.append(&tx.nonce);
+            stream.append(&big_int(&tx.gas_price));
+        }
+        _ => {
+            stream.append(&chain_id?);
+            stream.append(&tx.nonce);
+            stream.append(&big_int(&tx.max_priority_fee_per_gas));
+            stream.append(&big_int(&tx.max_fee_per_gas));
+        }
+    }
+    stream.append(&tx.gas_limit);
+    stream.append(&to);
+    stream.append(&big_int(&tx.value));
+    stream.append(&tx.input);
+    if tx.r#type != 0 {
+        append_access_list(&mut stream, &tx.access_list);
+    }
+    if tx.r#type == 3 {
+        stream.append(&big_int(&tx.blob_gas_fee_cap));
+        stream.begin_list(tx.blob_hashes.len());
+        for hash in &tx.blob_hashes {
+            stream.append(hash);
+        }
+    }
+    if tx.r#type == 4 {
+        stream.begin_list(tx.set_code_authorizations.len());
+        for auth in &tx.set_code_authorizations {
+            stream.begin_list(6);
+            stream.append(&uint(&auth.chain_id));
+            stream.append(&auth.address);
+            stream.append(&auth.nonce);
+            stream.append(&auth.v);
+            stream.append(&uint(&auth.r));
+            stream.append(&uint(&auth.s));
+        }
+    }
+    if signed {
+        stream.append(&uint(&tx.v));
+        stream.append(&uint(&tx.r));
+        stream.append(&uint(&tx.s));
+    } else if tx.r#type == 0 {
+        if let Some(chain_id) = chain_id {
+            stream.append(&chain_id);
+            stream.append_empty_data();
+            stream.append_empty_data();
+        }
+    }
+
+    let mut encoded = stream.out().to_vec();
+    if tx.r#type != 0 {
+        encoded.insert(0, u8::try_from(tx.r#type).ok()?);
+    }
+    Some(encoded)
+}
+
+/// Returns the canonical encoding of a transaction or None if its type isn't supported
+pub fn encode_transaction(tx: &pbcodec::TransactionTrace, chain_id: u64) -> Option<Vec<u8>> {
+    encode_fields(tx, Some(chain_id), true)
+}
+
+// legacy transactions carry the chain id in v since EIP-155, typed ones sign over y parity
+fn recovery_params(tx: &pbcodec::TransactionTrace, chain_id: Option<u64>) -> Option<(Option<u64>, u8)> {
+    let v = uint(&tx.v);
+    if v.len() > 8 {
+        return None;
+    }
+    let v = v.iter().fold(0u64, |acc, byte| acc << 8 | *byte as u64);
+    match (tx.r#type, v) {
+        (0, 27 | 28) => Some((None, (v - 27) as u8)),
+        (0, 35..) => Some((Some((v - 35) / 2), ((v - 35) % 2) as u8)),
+        (0, _) => None,
+        (_, 0 | 1) => Some((chain_id, v as u8)),
+        (_, 27 | 28) => Some((chain_id, (v - 27) as u8)),
+        _ => None,
+    }
+}
+
+/// Computes the hash which the sender of a transaction signed
+pub fn signing_hash(tx: &pbcodec::TransactionTrace, chain_id: Option<u64>) -> Option<[u8; 32]> {
+    let (chain_id, _) = recovery_params(tx, chain_id)?;
+    encode_fields(tx, chain_id, false).map(keccak256)
+}
+
+/// Recovers the uncompressed SEC1 encoded public key of the transaction sender.
+/// Typed transactions can only be recovered if the chain id is known.
+pub fn recover_public_key(tx: &pbcodec::TransactionTrace, chain_id: Option<u64>) -> anyhow::Result<Vec<u8>> {
+    let (_, recovery_id) = recovery_params(tx, chain_id).context("invalid signature v")?;
+    let hash = signing_hash(tx, chain_id).context("can't compute the signing hash")?;
+
+    let mut signature = [0u8; 64];
+    let (r, s) = (uint(&tx.r), uint(&tx.s));
+    anyhow::ensure!(r.len() <= 32 && s.len() <= 32, "invalid signature");
+    signature[32 - r.len()..32].copy_from_slice(r);
+    signature[64 - s.len()..].copy_from_slice(s);
+    let signature = Signature::from_slice(&signature)?;
+    let recovery_id = RecoveryId::from_byte(recovery_id).context("invalid recovery id")?;
+
+    let key = VerifyingKey::recover_from_prehash(&hash, &signature, recovery_id)?;
+    Ok(key.to_encoded_point(false).as_bytes().to_vec())
+}
+
+/// Returns the address which belongs to an uncompressed public key
+pub fn public_key_address(public_key: &[u8]) -> Vec<u8> {
+    keccak256(&public_key[1..])[12..].to_vec()
+}
+
+/// Computes the 2048-bit bloom filter of the log addresses and topics
+pub fn logs_bloom(logs: &[pbcodec::Log]) -> evm::Bloom {
+    let mut bloom = evm::Bloom::zero();
+    for log in logs {
+        bloom.accrue(BloomInput::Raw(&log.address));
+        for topic in &log.topics {
+            bloom.accrue(BloomInput::Raw(topic));
+        }
+    }
+    bloom
+}
+
+/// Returns the canonical encoding of a transaction receipt or None if it can't be restored
+pub fn encode_receipt(tx: &pbcodec::TransactionTrace) -> Option<Vec<u8>> {
+    let receipt = tx.receipt.as_ref()?;
+
+    let mut stream = RlpStream::new();
+    stream.begin_list(4);
+    // receipts before byzantium contain the state root instead of the status
+    if !receipt.state_root.is_empty() {
+        stream.append(&receipt.state_root);
+    } else if tx.status == pbcodec::TransactionTraceStatus::Succeeded as i32 {
+        stream.append(&1u8);
+    } else if tx.status == pbcodec::TransactionTraceStatus::Unknown as i32 {
+        return None;
+    } else {
+        stream.append_empty_data();
+    }
+    stream.append(&receipt.cumulative_gas_used);
+    stream.append(&receipt.logs_bloom);
+    stream.begin_list(receipt.logs.len());
+    for log in &receipt.logs {
+        stream.begin_list(3);
+        stream.append(&log.address);
+        stream.begin_list(log.topics.len());
+        for topic in &log.topics {
+            stream.append(topic);
+        }
+        stream.append(&log.data);
+    }
+
+    let mut encoded = stream.out().to_vec();
+    match tx.r#type {
+        0 => {}
+        // receipts of l2 specific transactions have extra fields
+        r#type @ 1..=4 => encoded.insert(0, u8::try_from(r#type).ok()?),
+        _ => return None,
+    }
+    Some(encoded)
+}
+
+fn check_root(
+    number: u64,
+    root: &'static str,
+    expected: &[u8],
+    values: Option<Vec<Vec<u8>>>,
+) -> anyhow::Result<()> {
+    let values = match values {
+        Some(values) => values,
+        None => {
+            ROOT_VERIFICATIONS.with_label_values(&[root, "skipped"]).inc();
+            return Ok(());
+        }
+    };
+    let computed = ordered_trie_root(values);
+    if computed.as_bytes() != expected {
+        ROOT_VERIFICATIONS.with_label_values(&[root, "mismatch"]).inc();
+        return Err(VerificationError::RootMismatch {
+            number,
+            root,
+            expected: prefix_hex::encode(expected),
+            computed: format!("{:?}", computed),
+        }
+        .into());
+    }
+    ROOT_VERIFICATIONS.with_label_values(&[root, "ok"]).inc();
+    Ok(())
+}
+
+/// Checks that transactions and receipts of a complete block match the roots of its header,
+/// the receipts root is skipped if the receipts can't be restored from the available data.
+pub fn verify_block_roots(
+    block: &pbcodec::Block,
+    chain_id: u64,
+    with_receipts: bool,
+    chain: ChainProfile,
+) -> anyhow::Result<()> {
+    let header = block.header.as_ref().context("no block header")?;
+
+    // bor state sync transactions aren't part of the roots
+    let transactions = block
+        .transaction_traces
+        .iter()
+        .filter(|tx| !chain.is_state_sync(tx))
+        .map(|tx| encode_transaction(tx, chain_id))
+        .collect();
+    check_root(block.number, "transactions", &header.transactions_root, transactions)?;
+
+    let receipts = block
+        .transaction_traces
+        .iter()
+        .filter(|tx| !chain.is_state_sync(tx))
+        .map(encode_receipt)
+        .collect::<Option<Vec<_>>>()
+        .filter(|_| with_receipts);
+    check_root(block.number, "receipts", &header.receipt_root, receipts)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_required_fields, encode_receipt, get_fork, ordered_trie_root, public_key_address, recover_public_key,
+        signing_hash, trie_root, verify_block_roots, verify_header_hash, Fork, VerificationError, EMPTY_ROOT,
+    };
+    use crate::firehose::ChainProfile;
+    use crate::datasource::{test_block, BlockHeader};
+    use crate::pbcodec;
+    use ethers_core::k256::ecdsa::SigningKey;
+
+    fn genesis() -> BlockHeader {
+        let header = test_block(
+            0,
+            "0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3".into(),
+            "0x0000000000000000000000000000000000000000000000000000000000000000".into(),
+        )
+        .header;
+        BlockHeader {
+            size: Some(540),
+            sha3_uncles: Some("0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347".into()),
+            miner: "0x0000000000000000000000000000000000000000".into(),
+            state_root: "0xd7f8974fb5ac78d9ac099b9ad5018bedc2ce0a72dad1827a1709da30580f0544".into(),
+            transactions_root: "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421".into(),
+            receipts_root: "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421".into(),
+            logs_bloom: format!("0x{}", "0".repeat(512)),
+            difficulty: "0x400000000".into(),
+            total_difficulty: Some("0x400000000".into()),
+            gas_limit: "0x1388".into(),
+            gas_used: "0x0".into(),
+            timestamp: 0,
+            extra_data: "0x11bbe8db4e347b4e8c937c1c8370e4b5ed33adb3db69cbdb7a38e1e50b1b82fa".into(),
+            mix_hash: Some("0x0000000000000000000000000000000000000000000000000000000000000000".into()),
+            nonce: Some("0x0000000000000042".into()),
+            ..header
+        }
+    }
+
+    #[test]
+    fn verify_genesis_hash() {
+        let mut header = genesis();
+        assert_eq!(get_fork(&header).unwrap(), Fork::Frontier);
+        verify_header_hash(&header).unwrap();
+
+        header.gas_limit = "0x1389".into();
+        let err = verify_header_hash(&header).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<VerificationError>(),
+            Some(VerificationError::HashMismatch { number: 0, fork: Fork::Frontier, .. })
+        ));
+    }
+
+    #[test]
+    fn require_canonical_header_fields() {
+        let mut header = genesis();
+        header.total_difficulty = None;
+        check_required_fields(&header).unwrap();
+
+        header.mix_hash = None;
+        assert!(matches!(
+            check_required_fields(&header),
+            Err(VerificationError::MissingField { number: 0, field: "mix hash" })
+        ));
+    }
+
+    #[test]
+    fn detect_fork_by_fields() {
+        let mut header = genesis();
+        header.base_fee_per_gas = Some("0x7".into());
+        assert_eq!(get_fork(&header).unwrap(), Fork::London);
+
+        header.withdrawals_root = Some(header.receipts_root.clone());
+        assert_eq!(get_fork(&header).unwrap(), Fork::Shanghai);
+
+        header.blob_gas_used = Some("0x0".into());
+        assert!(get_fork(&header).is_err());
+
+        header.excess_blob_gas = Some("0x0".into());
+        header.parent_beacon_block_root = Some(header.parent_hash.clone());
+        assert_eq!(get_fork(&header).unwrap(), Fork::Cancun);
+
+        header.base_fee_per_gas = None;
+        assert!(get_fork(&header).is_err());
+    }
+
+    fn hex(value: &str) -> Vec<u8> {
+        prefix_hex::decode(value).unwrap()
+    }
+
+    // the example transaction of EIP-155
+    fn eip155_transaction() -> pbcodec::TransactionTrace {
+        pbcodec::TransactionTrace {
+            to: hex("0x3535353535353535353535353535353535353535"),
+            nonce: 9,
+            gas_price: Some(pbcodec::BigInt { bytes: hex("0x04a817c800") }),
+            gas_limit: 21000,
+            value: Some(pbcodec::BigInt { bytes: hex("0x0de0b6b3a7640000") }),
+            v: vec![37],
+            r: hex("0x28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276"),
+            s: hex("0x67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"),
+            from: hex("0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn recover_legacy_sender() {
+        let tx = eip155_transaction();
+        assert_eq!(
+            signing_hash(&tx, None).unwrap().to_vec(),
+            hex("0xdaf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53")
+        );
+        let public_key = recover_public_key(&tx, None).unwrap();
+        assert_eq!(public_key.len(), 65);
+        assert_eq!(public_key_address(&public_key), tx.from);
+    }
+
+    #[test]
+    fn recover_typed_sender() {
+        let key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+        let mut tx = eip155_transaction();
+        tx.r#type = 2;
+        tx.max_fee_per_gas = tx.gas_price.take();
+        tx.max_priority_fee_per_gas = Some(pbcodec::BigInt { bytes: vec![1] });
+        tx.v = vec![];
+
+        let hash = signing_hash(&tx, Some(1)).unwrap();
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+        tx.v = vec![recovery_id.to_byte()];
+        tx.r = signature.r().to_bytes().to_vec();
+        tx.s = signature.s().to_bytes().to_vec();
+
+        let public_key = recover_public_key(&tx, Some(1)).unwrap();
+        assert_eq!(public_key_address(&public_key), tx.from);
+        assert!(recover_public_key(&tx, None).is_err());
+    }
+
+    #[test]
+    fn encode_pre_byzantium_receipt() {
+        let mut tx = pbcodec::TransactionTrace {
+            status: pbcodec::TransactionTraceStatus::Succeeded.into(),
+            receipt: Some(pbcodec::TransactionReceipt {
+                state_root: vec![0x11; 32],
+                cumulative_gas_used: 21000,
+                logs_bloom: vec![0; 256],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let expected = format!("0xf90128a0{}825208b90100{}c0", "11".repeat(32), "00".repeat(256));
+        assert_eq!(prefix_hex::encode(encode_receipt(&tx).unwrap()), expected);
+
+        // the status takes place of the state root since byzantium
+        tx.receipt.as_mut().unwrap().state_root = vec![];
+        let expected = format!("0xf9010801825208b90100{}c0", "00".repeat(256));
+        assert_eq!(prefix_hex::encode(encode_receipt(&tx).unwrap()), expected);
+    }
+
+    #[test]
+    fn compute_trie_roots() {
+        assert_eq!(format!("{:?}", ordered_trie_root(vec![])), EMPTY_ROOT);
+
+        let root = trie_root(vec![(b"A".to_vec(), vec![b'a'; 50])]);
+        assert_eq!(
+            format!("{:?}", root),
+            "0xd23786fb4a010da3ce639d66d5e904a11dbc02746d1ce25029e53290cabf28ab"
+        );
+
+        let root = trie_root(vec![
+            (b"doe".to_vec(), b"reindeer".to_vec()),
+            (b"dog".to_vec(), b"puppy".to_vec()),
+            (b"dogglesworth".to_vec(), b"cat".to_vec()),
+        ]);
+        assert_eq!(
+            format!("{:?}", root),
+            "0x8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"
+        );
+    }
+
+    #[test]
+    fn skip_state_sync_transactions_only_on_polygon() {
+        let block = pbcodec::Block {
+            header: Some(pbcodec::BlockHeader {
+                transactions_root: prefix_hex::decode(EMPTY_ROOT).unwrap(),
+                ..Default::default()
+            }),
+            transaction_traces: vec![pbcodec::TransactionTrace {
+                from: vec![0; 20],
+                to: vec![0; 20],
+                gas_price: Some(pbcodec::BigInt { bytes: vec![] }),
+                value: Some(pbcodec::BigInt { bytes: vec![] }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        verify_block_roots(&block, 137, false, ChainProfile::Polygon).unwrap();
+        let err = verify_block_roots(&block, 137, false, ChainProfile::Ethereum).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<VerificationError>(),
+            Some(VerificationError::RootMismatch { root: "transactions", .. })
+        ));
+    }
+}
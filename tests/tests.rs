@@ -6,7 +6,7 @@ use tokio_stream::StreamExt;
 
 use firehose_grpc::portal::Portal;
 use firehose_grpc::ds_portal::PortalDataSource;
-use firehose_grpc::firehose::Firehose;
+use firehose_grpc::firehose::{ChainProfile, ConversionOptions, Firehose};
 use firehose_grpc::pbcodec::Block;
 use firehose_grpc::pbfirehose::{Request, SingleBlockRequest};
 use firehose_grpc::pbfirehose::single_block_request::{Reference, BlockNumber};
@@ -20,14 +20,14 @@ impl TestFirehose {
     fn new() -> TestFirehose {
         let url = "https://portal.sqd.dev/datasets/ethereum-mainnet".into();
         let portal = Arc::new(Portal::new(url));
-        let portal_ds = Arc::new(PortalDataSource::new(portal));
-        let firehose = Firehose::new(portal_ds, None);
+        let portal_ds = Arc::new(PortalDataSource::new(portal, ChainProfile::default()));
+        let firehose = Firehose::new(Some(portal_ds), None, None, ConversionOptions::default(), 256).unwrap();
         TestFirehose { firehose }
     }
 
     async fn blocks(&self, req: &Request) -> anyhow::Result<Vec<Block>> {
         let mut blocks = vec![];
-        let stream = self.firehose.blocks(&req).await?;
+        let stream = self.firehose.blocks(req).await?;
         tokio::pin!(stream);
 
         while let Some(resp) = stream.try_next().await? {